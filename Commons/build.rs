@@ -0,0 +1,15 @@
+use std::process::Command;
+
+fn main() {
+    // Record the commit the binary is built from, when building from a git checkout
+    if let Ok(output) = Command::new("git").args(["rev-parse", "HEAD"]).output() {
+        if output.status.success() {
+            println!(
+                "cargo:rustc-env=GIT_COMMIT={}",
+                String::from_utf8_lossy(&output.stdout).trim()
+            );
+        }
+    }
+    println!("cargo:rerun-if-changed=../.git/HEAD");
+    println!("cargo:rerun-if-changed=../.git/refs");
+}
@@ -1,13 +1,15 @@
 use float_ord::FloatOrd;
 use rand::prelude::*;
 use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 use std::ops::{Index, IndexMut};
 
+use crate::config::InitMode;
 use crate::CONFIG;
 
 /// An action / action availible to an agent, tracking its own statistics
 ///
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Serialize, Deserialize)]
 pub struct Action {
     /// Number of resources to take
     num_resources: i32,
@@ -47,6 +49,14 @@ impl Action {
         return self.expected_value;
     }
 
+    pub fn get_times_chosen(&self) -> i32 {
+        self.times_chosen
+    }
+
+    pub fn set_times_chosen(&mut self, value: i32) {
+        self.times_chosen = value;
+    }
+
     pub fn set_expected_value(&mut self, value: f32) {
         self.expected_value = value;
     }
@@ -59,6 +69,7 @@ impl Action {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Actions {
     // Consider changing implementation to HashMap or other data structure
     actions: Vec<Action>,
@@ -66,25 +77,24 @@ pub struct Actions {
 
 /// Container for all availible actions. All 'non-cognitive' operations on action selection can be done here
 impl Actions {
-    pub fn new(num_actions: i32) -> Actions {
+    pub fn new(num_actions: i32, rng: &mut impl Rng) -> Actions {
         Actions {
-            actions: Self::init_actions(num_actions),
+            actions: Self::init_actions(num_actions, rng),
         }
     }
 
-    fn init_actions(num_actions: i32) -> Vec<Action> {
+    fn init_actions(num_actions: i32, rng: &mut impl Rng) -> Vec<Action> {
         let mut actions: Vec<Action> = Vec::with_capacity(num_actions as usize);
         for i in 0..num_actions {
-            // initialization strategy 
-            match CONFIG.rl_params.init_mode {
-                // 0: 0 with fuzzing [0, 0.01]
-                0 => actions.push(Action::new(i, rand::thread_rng().gen::<f32>() * 0.01, 0)),
-                // 1: optimistic inital values [5 + fuzzing]
-                1 => actions.push(Action::new(i, 5.0 + (rand::thread_rng().gen::<f32>() * 0.01), 0)),
-                // 2: 0.0 --> without tiebreaker will slide from max to min index 
-                2 => actions.push(Action::new(i, 0.0, 0)),
-                _ => actions.push(Action::new(i, rand::thread_rng().gen::<f32>() * 0.01, 0)),
-            }
+            let expected_value = match &CONFIG.rl_params.init_mode {
+                InitMode::Random { scale } => rng.gen::<f32>() * scale,
+                InitMode::Optimistic { value, scale } => value + rng.gen::<f32>() * scale,
+                // without tiebreaker will slide from max to min index
+                InitMode::Constant { value } => *value,
+                // replaced by the Q-tables read from the file
+                InitMode::FromFile { .. } => 0.0,
+            };
+            actions.push(Action::new(i, expected_value, 0));
         }
         return actions;
     }
@@ -98,12 +108,34 @@ impl Actions {
             .unwrap();
     }
 
+    pub fn max_ev_action_idx(&self) -> usize {
+        self.actions
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, action)| FloatOrd(action.expected_value))
+            .map(|(idx, _)| idx)
+            .unwrap()
+    }
+
     // this doesn't need to be mutable. We can get this action as non-mutable TODO
     // and then in the higher layer get the action index
     // and use that index to get a mutable version of that action to the higher layer
     // by having a get_mut_action(&self mut, action_idx) -> &mut action function here low level.
-    pub fn random_action(&mut self) -> &mut Action {
-        return self.actions.choose_mut(&mut rand::thread_rng()).unwrap();
+    pub fn random_action(&mut self, rng: &mut impl Rng) -> &mut Action {
+        return self.actions.choose_mut(rng).unwrap();
+    }
+
+    /// The action taking the most resources
+    pub fn largest_action(&mut self) -> &mut Action {
+        self.actions.last_mut().unwrap()
+    }
+
+    /// Take over `fraction` of the expected values of `other`, keeping the rest of our own
+    pub fn blend(&mut self, other: &Actions, fraction: f32) {
+        for (action, other_action) in self.actions.iter_mut().zip(&other.actions) {
+            action.expected_value =
+                fraction * other_action.expected_value + (1. - fraction) * action.expected_value;
+        }
     }
 
     pub fn report(&self) {
@@ -1,11 +1,17 @@
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
 use crate::CONFIG;
 //use std::num;
 use crate::agent::actions::{Action, Actions};
-use crate::agent::rl_algs::{bandit, qlearning, update_bandit, update_qlearning};
-use crate::agent::structs::{AgentState, AgentType, QTable};
+use crate::agent::models::AgentModel;
+use crate::agent::rl_algs::{bandit, qlearning, update_bandit, update_qlearning, update_towards};
+use crate::agent::structs::{AgentState, AgentType, OthersHarvest, QTable, ResourceState};
+use crate::calculator::sustainable_share;
 use crate::config::RLParameters;
 
 /// Cognitive component of the agent. All 'cognitive' operations / decision making of actions can be done here
+#[derive(Serialize, Deserialize)]
 pub struct AgentBrain {
     actions: Actions,
     pub q_table: QTable, // possibly should be hidden somewhere, merged with actions so its only visible if its a qlearning agent
@@ -14,60 +20,412 @@ pub struct AgentBrain {
     previous_state: Option<AgentState>,
     behaviour_type: AgentType,
     rlparameters: RLParameters,
+    /// Exploration rate while evaluating, learning is frozen when set
+    #[serde(skip)]
+    evaluation_epsilon: Option<f32>,
+    /// Sum of absolute TD errors and number of updates since the last diagnostics
+    #[serde(skip)]
+    td_error_sum: f32,
+    #[serde(skip)]
+    n_updates: i32,
+    /// Pool at the last state update, for the threshold baseline
+    #[serde(default)]
+    pool: i32,
+    /// What the sustainable baseline takes
+    #[serde(default)]
+    sustainable_share: i32,
+    /// What the others asked for in the previous epoch, for the reciprocal types
+    #[serde(skip)]
+    others: Option<OthersHarvest>,
+    /// Whether a grim trigger agent saw another agent take more than its share this generation
+    #[serde(default)]
+    triggered: bool,
+    /// Values of the signals for the bandit and Q-learning agents, when communication is enabled
+    #[serde(default)]
+    signal_actions: Option<Actions>,
+    #[serde(default)]
+    signal_table: Option<QTable>,
+    /// The signal emitted in this epoch, and the distribution of the signals heard
+    #[serde(default)]
+    signal_choice: Option<SignalChoice>,
+    #[serde(default)]
+    heard_signals: Option<Vec<ResourceState>>,
+    /// The mean reputation of the others after the last epoch
+    #[serde(default)]
+    others_reputation: Option<f32>,
+    /// Values of the quota options for the bandit and Q-learning agents, when voting is enabled
+    #[serde(default)]
+    vote_actions: Option<Actions>,
+    #[serde(default)]
+    vote_table: Option<QTable>,
+    /// The vote of the current term
+    #[serde(default)]
+    ballot: Option<Ballot>,
+}
+
+/// A signal, with the state it was chosen in. Hearing the signals changes the state before
+/// the signal is credited.
+#[derive(Serialize, Deserialize, Clone)]
+struct SignalChoice {
+    signal: i32,
+    state: AgentState,
+}
+
+/// A vote for a quota option, credited with the mean reward per epoch of the term it decided
+#[derive(Serialize, Deserialize, Clone)]
+struct Ballot {
+    option: usize,
+    state: AgentState,
+    reward_sum: i32,
+    epochs: i32,
 }
 
 impl AgentBrain {
-    pub fn new(num_actions: i32, agent_type: AgentType) -> AgentBrain {
+    pub fn new(num_actions: i32, agent_type: AgentType, rng: &mut impl Rng) -> AgentBrain {
         AgentBrain {
-            actions: Actions::new(num_actions),
-            q_table: QTable::new(num_actions),
+            actions: Actions::new(num_actions, rng),
+            q_table: QTable::new(num_actions, rng),
             last_reward: 0,
             current_state: None,
             previous_state: None,
             behaviour_type: agent_type,
-            rlparameters: CONFIG.rl_params,
+            rlparameters: CONFIG.rl_params.clone(),
+            evaluation_epsilon: None,
+            td_error_sum: 0.,
+            n_updates: 0,
+            pool: 0,
+            sustainable_share: if agent_type.uses_sustainable_share() {
+                sustainable_share(&CONFIG.experiment).min(num_actions - 1)
+            } else {
+                0
+            },
+            others: None,
+            triggered: false,
+            signal_actions: if CONFIG.communication.enabled() {
+                Some(Actions::new(CONFIG.communication.vocabulary, rng))
+            } else {
+                None
+            },
+            signal_table: if CONFIG.communication.enabled() {
+                Some(QTable::new(CONFIG.communication.vocabulary, rng))
+            } else {
+                None
+            },
+            signal_choice: None,
+            heard_signals: None,
+            others_reputation: None,
+            vote_actions: if CONFIG.voting.enabled() {
+                Some(Actions::new(CONFIG.voting.quotas.len() as i32, rng))
+            } else {
+                None
+            },
+            vote_table: if CONFIG.voting.enabled() {
+                Some(QTable::new(CONFIG.voting.quotas.len() as i32, rng))
+            } else {
+                None
+            },
+            ballot: None,
+        }
+    }
+
+    /// Take over `fraction` of the learned values of a parent, keeping the rest of the fresh ones
+    pub fn inherit(&mut self, parent: &AgentBrain, fraction: f32) {
+        self.actions.blend(&parent.actions, fraction);
+        self.q_table.blend(&parent.q_table, fraction);
+        if let (Some(own), Some(parents)) = (&mut self.signal_actions, &parent.signal_actions) {
+            own.blend(parents, fraction);
         }
+        if let (Some(own), Some(parents)) = (&mut self.signal_table, &parent.signal_table) {
+            own.blend(parents, fraction);
+        }
+        if let (Some(own), Some(parents)) = (&mut self.vote_actions, &parent.vote_actions) {
+            own.blend(parents, fraction);
+        }
+        if let (Some(own), Some(parents)) = (&mut self.vote_table, &parent.vote_table) {
+            own.blend(parents, fraction);
+        }
+    }
+
+    pub fn agent_type(&self) -> AgentType {
+        self.behaviour_type
+    }
+
+    /// Start from previously learned values instead of freshly initialised ones
+    pub fn load_model(&mut self, model: &AgentModel) {
+        self.q_table = model.q_table.clone();
+        self.actions = model.actions.clone();
     }
 
-    pub fn decide_action(&mut self) -> i32 {
+    pub fn decide_action(&mut self, rng: &mut impl Rng) -> i32 {
         // replace egreedy with rl alg
-        let chosen_action = self.decision_behaviour_interface();
-        // Increment amount this action has been chosen
-        chosen_action.increment_chosen(1);
+        let evaluating = self.evaluation_epsilon.is_some();
+        let chosen_action = self.decision_behaviour_interface(rng);
+        // Increment amount this action has been chosen, evaluation leaves the tables untouched
+        if !evaluating {
+            chosen_action.increment_chosen(1);
+        }
         // Return the chosen integer of resources
         return chosen_action.get_num_resources();
     }
 
-    fn decision_behaviour_interface(&mut self) -> &mut Action {
+    fn decision_behaviour_interface(&mut self, rng: &mut impl Rng) -> &mut Action {
+        let epsilon = self.evaluation_epsilon.unwrap_or(self.rlparameters.epsilon);
         match self.behaviour_type {
-            AgentType::BANDIT => bandit(&mut self.actions, self.rlparameters.epsilon),
+            AgentType::BANDIT => bandit(&mut self.actions, epsilon, rng),
             AgentType::QLEARNING => qlearning(
                 &mut self.q_table,
-                &self.current_state.unwrap().to_string(),
-                self.rlparameters.epsilon,
+                &self.current_state.as_ref().unwrap().to_string(),
+                epsilon,
+                rng,
             ),
+            AgentType::FIXED { amount } => &mut self.actions[amount as usize],
+            AgentType::SUSTAINABLE => &mut self.actions[self.sustainable_share as usize],
+            AgentType::THRESHOLD { amount, threshold } => {
+                if self.pool as f32 > threshold * CONFIG.experiment.max_pool_size as f32 {
+                    &mut self.actions[amount as usize]
+                } else {
+                    &mut self.actions[0]
+                }
+            }
+            AgentType::RANDOM => self.actions.random_action(rng),
+            AgentType::GREEDY => self.actions.largest_action(),
+            AgentType::RECIPROCAL => {
+                let amount = self
+                    .others
+                    .map_or(self.sustainable_share, |others| others.mean.round() as i32);
+                &mut self.actions[amount as usize]
+            }
+            AgentType::GRIM if self.triggered => self.actions.largest_action(),
+            AgentType::GRIM => &mut self.actions[self.sustainable_share as usize],
+            AgentType::CONFORMIST => {
+                let amount = self
+                    .others
+                    .map_or(self.sustainable_share, |others| others.majority);
+                &mut self.actions[amount as usize]
+            }
+        }
+    }
+
+    /// Choose the signal to emit before harvesting. Learners choose it like their actions,
+    /// the baselines signal at random.
+    pub fn decide_signal(&mut self, rng: &mut impl Rng) -> i32 {
+        let evaluating = self.evaluation_epsilon.is_some();
+        let epsilon = self.evaluation_epsilon.unwrap_or(self.rlparameters.epsilon);
+        let state = self.current_state.clone().unwrap();
+        let signal = match (
+            self.behaviour_type,
+            &mut self.signal_actions,
+            &mut self.signal_table,
+        ) {
+            (AgentType::BANDIT, Some(signal_actions), _) => {
+                Some(bandit(signal_actions, epsilon, rng))
+            }
+            (AgentType::QLEARNING, _, Some(signal_table)) => {
+                Some(qlearning(signal_table, &state.to_string(), epsilon, rng))
+            }
+            _ => None,
         }
+        .map_or_else(
+            || rng.gen_range(0..CONFIG.communication.vocabulary),
+            |chosen_signal| {
+                if !evaluating {
+                    chosen_signal.increment_chosen(1);
+                }
+                chosen_signal.get_num_resources()
+            },
+        );
+        self.signal_choice = Some(SignalChoice { signal, state });
+        signal
+    }
+
+    /// Hear the distribution of the signals of this epoch's signalling phase, which becomes
+    /// part of the state
+    pub fn hear(&mut self, signals: &[ResourceState]) {
+        self.heard_signals = Some(signals.to_vec());
+        self.current_state = self
+            .current_state
+            .take()
+            .map(|state| state.with_signals(Some(signals.to_vec())));
+    }
+
+    /// Vote for one of the quota options, closing the term of the previous vote. Learners
+    /// choose like their actions, the baselines vote at random.
+    pub fn decide_vote(&mut self, rng: &mut impl Rng) -> usize {
+        self.close_ballot();
+        let evaluating = self.evaluation_epsilon.is_some();
+        let epsilon = self.evaluation_epsilon.unwrap_or(self.rlparameters.epsilon);
+        let state = self.current_state.clone().unwrap();
+        let option = match (
+            self.behaviour_type,
+            &mut self.vote_actions,
+            &mut self.vote_table,
+        ) {
+            (AgentType::BANDIT, Some(vote_actions), _) => Some(bandit(vote_actions, epsilon, rng)),
+            (AgentType::QLEARNING, _, Some(vote_table)) => {
+                Some(qlearning(vote_table, &state.to_string(), epsilon, rng))
+            }
+            _ => None,
+        }
+        .map_or_else(
+            || rng.gen_range(0..CONFIG.voting.quotas.len()),
+            |chosen_option| {
+                if !evaluating {
+                    chosen_option.increment_chosen(1);
+                }
+                chosen_option.get_num_resources() as usize
+            },
+        );
+        self.ballot = Some(Ballot {
+            option,
+            state,
+            reward_sum: 0,
+            epochs: 0,
+        });
+        option
+    }
+
+    /// Credit the vote of the ended term with its mean reward per epoch
+    pub fn close_ballot(&mut self) {
+        let ballot = match self.ballot.take() {
+            Some(ballot) if ballot.epochs > 0 => ballot,
+            _ => return,
+        };
+        if self.rlparameters.freeze_learning
+            || self.evaluation_epsilon.is_some()
+            || !self.behaviour_type.learns()
+        {
+            return;
+        }
+        let mean_reward = ballot.reward_sum as f32 / ballot.epochs as f32;
+        let option = match (
+            self.behaviour_type,
+            &mut self.vote_actions,
+            &mut self.vote_table,
+        ) {
+            (AgentType::BANDIT, Some(vote_actions), _) => &mut vote_actions[ballot.option],
+            (AgentType::QLEARNING, _, Some(vote_table)) => {
+                vote_table.get_action_mut(&ballot.state.to_string(), ballot.option)
+            }
+            _ => return,
+        };
+        update_towards(option, mean_reward, self.rlparameters.alpha);
+    }
+
+    /// See the mean reputation of the others, None without others
+    pub fn observe_reputation(&mut self, others_reputation: Option<f32>) {
+        self.others_reputation = others_reputation;
+    }
+
+    /// See what the others asked for in the last epoch
+    pub fn observe(&mut self, others: Option<OthersHarvest>) {
+        if let (AgentType::GRIM, Some(others)) = (self.behaviour_type, others) {
+            if others.max > self.sustainable_share {
+                self.triggered = true;
+            }
+        }
+        self.others = others;
+    }
+
+    /// Start a new generation without memories of the others
+    pub fn forget_others(&mut self) {
+        self.others = None;
+        self.triggered = false;
+        self.signal_choice = None;
+        self.heard_signals = None;
+        self.others_reputation = None;
     }
 
     pub fn update_ev(&mut self, action_idx: usize) {
+        if let Some(ballot) = &mut self.ballot {
+            ballot.reward_sum += self.last_reward;
+            ballot.epochs += 1;
+        }
         self.update_behaviour_interface(action_idx);
     }
 
     fn update_behaviour_interface(&mut self, action_idx: usize) {
-        match self.behaviour_type {
+        if self.rlparameters.freeze_learning
+            || self.evaluation_epsilon.is_some()
+            || !self.behaviour_type.learns()
+        {
+            return;
+        }
+        let td_error = match self.behaviour_type {
             AgentType::BANDIT => update_bandit(&mut self.actions, action_idx, self.last_reward),
             AgentType::QLEARNING => update_qlearning(
                 &mut self.q_table,
-                &self.previous_state.unwrap(),
-                &self.current_state.unwrap(),
+                self.previous_state.as_ref().unwrap(),
+                self.current_state.as_ref().unwrap(),
                 action_idx,
                 self.last_reward,
                 self.rlparameters.alpha,
                 self.rlparameters.gamma,
             ),
+            _ => return,
+        };
+        self.td_error_sum += td_error.abs();
+        self.n_updates += 1;
+        self.update_signal_values();
+    }
+
+    /// Credit the signal emitted in this epoch, in the state it was chosen in, with the reward
+    /// of the epoch
+    fn update_signal_values(&mut self) {
+        let choice = match &self.signal_choice {
+            Some(choice) => choice,
+            None => return,
+        };
+        let signal = choice.signal as usize;
+        match (
+            self.behaviour_type,
+            &mut self.signal_actions,
+            &mut self.signal_table,
+        ) {
+            (AgentType::BANDIT, Some(signal_actions), _) => {
+                update_bandit(signal_actions, signal, self.last_reward);
+            }
+            (AgentType::QLEARNING, _, Some(signal_table)) => {
+                update_qlearning(
+                    signal_table,
+                    &choice.state,
+                    self.current_state.as_ref().unwrap(),
+                    signal,
+                    self.last_reward,
+                    self.rlparameters.alpha,
+                    self.rlparameters.gamma,
+                );
+            }
+            _ => (),
+        }
+    }
+
+    /// Return the summed absolute TD error and number of updates, and start counting anew
+    pub fn take_td_errors(&mut self) -> (f32, i32) {
+        let td_errors = (self.td_error_sum, self.n_updates);
+        self.td_error_sum = 0.;
+        self.n_updates = 0;
+        td_errors
+    }
+
+    /// The action with the highest expected value in each state,
+    /// or the single best action for a bandit. Baselines have no policy to learn.
+    pub fn greedy_policy(&self) -> Vec<usize> {
+        match self.behaviour_type {
+            AgentType::BANDIT => vec![self.actions.max_ev_action_idx()],
+            AgentType::QLEARNING => self.q_table.greedy_policy(),
+            _ => Vec::new(),
         }
     }
 
+    /// Enter evaluation with the given exploration rate, or return to learning with `None`
+    pub fn set_evaluation(&mut self, epsilon: Option<f32>) {
+        self.evaluation_epsilon = epsilon;
+    }
+
+    pub fn is_evaluating(&self) -> bool {
+        self.evaluation_epsilon.is_some()
+    }
+
     pub fn set_last_reward(&mut self, value: i32) {
         self.last_reward = value;
     }
@@ -81,9 +439,36 @@ impl AgentBrain {
     }
 
     pub fn update_state(&mut self, pool: i32, score: i32) {
+        self.pool = pool;
         self.previous_state = self.current_state.clone();
         // better encapsulate this, agent brain should not know the size of the pool
-        self.current_state = Some(AgentState::from_values(pool, score));
+        // The heard signals stay part of the state until the next signalling phase
+        self.current_state = Some(
+            AgentState::from_values(pool, score)
+                .with_signals(self.state_signals())
+                .with_reputation(self.state_reputation()),
+        );
+    }
+
+    /// The signal part of the state: the distribution of the last signals heard, no signals
+    /// before the first signalling phase
+    fn state_signals(&self) -> Option<Vec<ResourceState>> {
+        if CONFIG.communication.enabled() {
+            Some(self.heard_signals.clone().unwrap_or_else(|| {
+                vec![ResourceState::LOW; CONFIG.communication.vocabulary as usize]
+            }))
+        } else {
+            None
+        }
+    }
+
+    /// The reputation part of the state, a neutral reputation before the first judgement
+    fn state_reputation(&self) -> Option<f32> {
+        if CONFIG.reputation.enabled {
+            Some(self.others_reputation.unwrap_or(0.))
+        } else {
+            None
+        }
     }
 
     pub fn report(&self) {
@@ -91,6 +476,7 @@ impl AgentBrain {
         match self.behaviour_type {
             AgentType::BANDIT => self.report_action_evs(),
             AgentType::QLEARNING => self.report_q_table(),
+            _ => println!("{:?} baseline", self.behaviour_type),
         }
     }
 
@@ -116,6 +502,14 @@ impl AgentBrain {
     }
 
     pub fn get_current_state(&self) -> Option<AgentState> {
-        self.current_state
+        self.current_state.clone()
+    }
+
+    pub fn get_q_table(&self) -> &QTable {
+        &self.q_table
+    }
+
+    pub fn get_actions(&self) -> &Actions {
+        &self.actions
     }
 }
@@ -1,25 +1,44 @@
 pub mod actions;
 pub mod agent_brain;
+pub mod models;
 pub mod rl_algs;
 pub mod structs;
 
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
 use self::agent_brain::AgentBrain;
-use self::structs::{AgentState, AgentType};
+use self::models::AgentModel;
+use self::structs::{AgentState, AgentType, HarvestObservation, QTable, ResourceState};
+use crate::CONFIG;
 
 /// The state of an agent, either alive or dead
-#[derive(PartialEq, Eq)]
+#[derive(PartialEq, Eq, Serialize, Deserialize)]
 pub enum AgentVitalState {
     ALIVE,
     DEAD,
 }
 
 /// An agent in the ToTC simulation
+#[derive(Serialize, Deserialize)]
 pub struct Agent {
     pub id: i32,
     score: i32,
     pub days_lived: i32,
+    /// Resources taken from the commons this generation
+    pub harvested: i32,
+    /// Number of deaths this generation, not counting evaluation generations
+    pub times_died: i32,
+    /// Number of times each action was chosen this generation
+    action_counts: Vec<i32>,
     vitals: AgentVitalState,
     planned_action: i32,
+    /// How well the agent kept to the harvest norm, from -1 (never) to 1 (always)
+    #[serde(default)]
+    pub reputation: f32,
+    /// Sum of the reputations after each judgement this generation
+    #[serde(default)]
+    reputation_sum: f32,
     brain: AgentBrain,
 }
 
@@ -30,22 +49,49 @@ impl Agent {
     ///
     /// * `id`    - An unsigned int to uniquely identify this agent
     /// * `score` - An integer that represents the agent's starting score
-    pub fn new(id: i32, score: Option<i32>, n_actions: i32) -> Agent {
+    /// * `agent_type` - How the agent chooses its actions
+    /// * `rng`   - Draws the initial values of the agent's Q-table
+    pub fn new(
+        id: i32,
+        score: Option<i32>,
+        n_actions: i32,
+        agent_type: AgentType,
+        rng: &mut impl Rng,
+    ) -> Agent {
         Agent {
             id,
             // Default score value 0
             score: score.unwrap_or(0),
             days_lived: 0,
+            harvested: 0,
+            times_died: 0,
+            action_counts: vec![0; n_actions as usize],
             vitals: AgentVitalState::ALIVE,
             planned_action: 0,
-            brain: AgentBrain::new(n_actions, AgentType::QLEARNING),
+            reputation: 0.,
+            reputation_sum: 0.,
+            brain: AgentBrain::new(n_actions, agent_type, rng),
         }
     }
 
+    /// Replace the agent's learned values with those of a saved model
+    pub fn load_model(&mut self, model: &AgentModel) {
+        self.brain.load_model(model);
+    }
+
     /// Choose how many resources to take
-    pub fn decide_action(&mut self) {
+    pub fn decide_action(&mut self, rng: &mut impl Rng) {
         // Q-Learning decision process
-        self.planned_action = self.brain.decide_action();
+        self.planned_action = self.brain.decide_action(rng);
+        self.action_counts[self.planned_action as usize] += 1;
+    }
+    /// Choose the signal to emit in the signalling phase before harvesting
+    pub fn decide_signal(&mut self, rng: &mut impl Rng) -> i32 {
+        self.brain.decide_signal(rng)
+    }
+    /// Hear the distribution of the signals of the signalling phase
+    pub fn hear(&mut self, signals: &[ResourceState]) {
+        self.brain.hear(signals);
     }
     /// Tell how many resource I want
     pub fn desired_resources(&self) -> i32 {
@@ -54,6 +100,7 @@ impl Agent {
     /// Receive the resources, update the EV from the last action
     pub fn get_resources(&mut self, value: i32) {
         self.score += value;
+        self.harvested += value;
         self.brain.set_last_reward(value);
     }
     /// Consume resources to stay alive, or perish if they are out
@@ -62,18 +109,51 @@ impl Agent {
         self.brain.decrease_last_reward(value);
         if self.score < 0 {
             self.die();
+        } else {
+            self.days_lived += 1;
         }
     }
     ///  Agent dies 
     pub fn die(&mut self) {
         self.vitals = AgentVitalState::DEAD;
+        self.count_death();
         self.brain.death_punishment();
     }
 
+    /// Die of old age. Unlike starving, this is no fault of the agent, so it is not punished.
+    pub fn die_of_age(&mut self) {
+        if self.vitals == AgentVitalState::ALIVE {
+            self.vitals = AgentVitalState::DEAD;
+            self.count_death();
+        }
+    }
+
+    fn count_death(&mut self) {
+        if !self.brain.is_evaluating() {
+            self.times_died += 1;
+        }
+    }
+
+    /// Make way for a newcomer in the agent's place, who inherits the configured fractions of
+    /// its learned values and score. The statistics of the place carry on.
+    pub fn replace_by_newcomer(&mut self, n_actions: i32, rng: &mut impl Rng) {
+        let ageing = &CONFIG.ageing;
+        // Credit the vote of the term so far, before the newcomer inherits the vote values
+        self.brain.close_ballot();
+        let mut brain = AgentBrain::new(n_actions, self.brain.agent_type(), rng);
+        brain.inherit(&self.brain, ageing.inherit_values);
+        self.brain = brain;
+        self.score = (self.score as f32 * ageing.inherit_score).round() as i32;
+        self.days_lived = 0;
+        self.reputation = 0.;
+        self.vitals = AgentVitalState::ALIVE;
+    }
+
     /// Manually kill an agent, E.G. when the commons are depleted
     pub fn kill(&mut self) {
         if self.vitals == AgentVitalState::ALIVE { // What is dead may never die
             self.vitals = AgentVitalState::DEAD;
+            self.count_death();
             self.brain.death_punishment();
         }
     }
@@ -83,10 +163,53 @@ impl Agent {
         self.brain.update_ev(self.planned_action as usize);
     }
 
+    /// Switch between evaluating with a fixed exploration rate and learning
+    pub fn set_evaluation(&mut self, epsilon: Option<f32>) {
+        self.brain.set_evaluation(epsilon);
+    }
+
     pub fn update_state(&mut self, pool_value: i32) {
         self.brain.update_state(pool_value, self.score);
     }
 
+    /// See what the other agents asked for in the epoch in which this agent asked for its planned action
+    pub fn observe(&mut self, observation: &HarvestObservation) {
+        self.brain.observe(observation.others(self.planned_action));
+    }
+
+    /// Vote for one of the quota options, returning its index
+    pub fn decide_vote(&mut self, rng: &mut impl Rng) -> usize {
+        self.brain.decide_vote(rng)
+    }
+
+    /// Credit the last vote with the rewards of its term, at the end of a generation
+    pub fn close_ballot(&mut self) {
+        self.brain.close_ballot();
+    }
+
+    /// Judge the amount asked for this epoch against the norm, moving the reputation
+    /// towards 1 when it was at most the norm and towards -1 otherwise
+    pub fn update_reputation(&mut self, norm: i32, rate: f32) {
+        let conduct = if self.planned_action <= norm { 1. } else { -1. };
+        self.reputation += rate * (conduct - self.reputation);
+        self.reputation_sum += self.reputation;
+    }
+
+    /// See the mean reputation of the others
+    pub fn observe_reputation(&mut self, others_reputation: Option<f32>) {
+        self.brain.observe_reputation(others_reputation);
+    }
+
+    /// Mean reputation over this generation's judgements, one per epoch harvested
+    pub fn mean_reputation(&self) -> f32 {
+        let n_judged: i32 = self.action_counts.iter().sum();
+        if n_judged > 0 {
+            self.reputation_sum / n_judged as f32
+        } else {
+            0.
+        }
+    }
+
     pub fn is_alive(&self) -> bool {
         return self.vitals == AgentVitalState::ALIVE;
     }
@@ -94,7 +217,14 @@ impl Agent {
     pub fn revive(&mut self) {
         //println!("{}", self.score);
         self.score = 0;
+        self.days_lived = 0;
+        self.harvested = 0;
+        self.times_died = 0;
+        self.action_counts.iter_mut().for_each(|count| *count = 0);
         self.vitals = AgentVitalState::ALIVE;
+        self.reputation = 0.;
+        self.reputation_sum = 0.;
+        self.brain.forget_others();
     }
 
     pub fn report(&self) {
@@ -114,4 +244,24 @@ impl Agent {
     pub fn get_current_state(&self) -> Option<AgentState> {
         self.brain.get_current_state()
     }
+
+    pub fn take_td_errors(&mut self) -> (f32, i32) {
+        self.brain.take_td_errors()
+    }
+
+    pub fn greedy_policy(&self) -> Vec<usize> {
+        self.brain.greedy_policy()
+    }
+
+    pub fn get_q_table(&self) -> &QTable {
+        self.brain.get_q_table()
+    }
+
+    pub fn get_score(&self) -> i32 {
+        self.score
+    }
+
+    pub fn get_action_counts(&self) -> &Vec<i32> {
+        &self.action_counts
+    }
 }
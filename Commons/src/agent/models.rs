@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::agent::actions::Actions;
+use crate::agent::structs::{AgentState, QTable};
+use crate::agent::Agent;
+
+/// Bump this whenever the layout of the model file changes
+pub const MODEL_FILE_VERSION: u32 = 1;
+
+/// The learned values of a single agent: its Q-table and its bandit actions
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AgentModel {
+    pub id: i32,
+    pub q_table: QTable,
+    pub actions: Actions,
+}
+
+#[derive(Serialize)]
+struct ModelFileRef<'a> {
+    version: u32,
+    agents: Vec<AgentModelRef<'a>>,
+}
+
+#[derive(Serialize)]
+struct AgentModelRef<'a> {
+    id: i32,
+    q_table: &'a QTable,
+    actions: &'a Actions,
+}
+
+#[derive(Deserialize)]
+struct ModelFile {
+    version: u32,
+    agents: Vec<AgentModel>,
+}
+
+/// Write the learned values of all agents to a json file, ordered by agent id
+pub fn save_models(agents: &[Agent], path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut models: Vec<AgentModelRef> = agents
+        .iter()
+        .map(|agent| AgentModelRef {
+            id: agent.id,
+            q_table: agent.brain.get_q_table(),
+            actions: agent.brain.get_actions(),
+        })
+        .collect();
+    models.sort_by_key(|model| model.id);
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(
+        &mut writer,
+        &ModelFileRef {
+            version: MODEL_FILE_VERSION,
+            agents: models,
+        },
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Read the agent models written by `save_models`, checking they fit the configured actions
+pub fn load_models(path: &Path, n_actions: i32) -> Result<Vec<AgentModel>, Box<dyn Error>> {
+    let model_file: ModelFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    if model_file.version != MODEL_FILE_VERSION {
+        return Err(format!(
+            "Model file {} has version {}, expected version {}",
+            path.display(),
+            model_file.version,
+            MODEL_FILE_VERSION
+        )
+        .into());
+    }
+    if model_file.agents.is_empty() {
+        return Err(format!("Model file {} holds no agents", path.display()).into());
+    }
+    if let Some(model) = model_file
+        .agents
+        .iter()
+        .find(|model| model.q_table.n_actions != n_actions)
+    {
+        return Err(format!(
+            "Agent {} in model file {} has {} actions, the configuration asks for {}",
+            model.id,
+            path.display(),
+            model.q_table.n_actions,
+            n_actions
+        )
+        .into());
+    }
+    // The states differ with and without communication
+    let state_keys = AgentState::state_keys();
+    if let Some(model) = model_file.agents.iter().find(|model| {
+        state_keys
+            .iter()
+            .any(|key| !model.q_table.state_action_pairs.contains_key(key))
+    }) {
+        return Err(format!(
+            "Agent {} in model file {} lacks states of this configuration, which depend on the communication and reputation settings",
+            model.id,
+            path.display()
+        )
+        .into());
+    }
+    Ok(model_file.agents)
+}
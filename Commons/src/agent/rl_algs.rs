@@ -2,10 +2,16 @@ use crate::agent::actions::{Action, Actions};
 use crate::agent::structs::{AgentState, QTable};
 use rand::Rng;
 
-pub fn qlearning<'a>(q_table: &'a mut QTable, state: &String, epsilon: f32) -> &'a mut Action {
-    epsilon_greedy(q_table.get_mut(state), epsilon)
+pub fn qlearning<'a>(
+    q_table: &'a mut QTable,
+    state: &String,
+    epsilon: f32,
+    rng: &mut impl Rng,
+) -> &'a mut Action {
+    epsilon_greedy(q_table.get_mut(state), epsilon, rng)
 }
 
+/// Q-learning update, returns the temporal difference error
 pub fn update_qlearning(
     q_table: &mut QTable,
     old_state: &AgentState,
@@ -14,7 +20,7 @@ pub fn update_qlearning(
     reward: i32,
     alpha: f32,
     gamma: f32,
-) {
+) -> f32 {
     // from value
     let old_ev: f32 = q_table.get(&old_state.to_string())[action_idx].get_expected_value();
     // off-policy best new action
@@ -23,28 +29,44 @@ pub fn update_qlearning(
         .max_ev_action()
         .get_expected_value();
     // calculate new ev
-    let new_ev: f32 = old_ev + (alpha * (reward as f32 + (gamma * max_next_ev) - old_ev));
+    let td_error: f32 = reward as f32 + (gamma * max_next_ev) - old_ev;
+    let new_ev: f32 = old_ev + (alpha * td_error);
     // update ev
     q_table.get_mut(&old_state.to_string())[action_idx].set_expected_value(new_ev);
+    td_error
 }
 
-pub fn bandit(actions: &mut Actions, epsilon: f32) -> &mut Action {
-    epsilon_greedy(actions, epsilon)
+/// Move the expected value of `action` a step towards `target`, returns the prediction error
+pub fn update_towards(action: &mut Action, target: f32, stepsize: f32) -> f32 {
+    let error = target - action.get_expected_value();
+    action.set_expected_value(action.get_expected_value() + stepsize * error);
+    error
 }
 
-pub fn update_bandit(actions: &mut Actions, action_idx: usize, reward: i32) {
+pub fn bandit<'a>(actions: &'a mut Actions, epsilon: f32, rng: &mut impl Rng) -> &'a mut Action {
+    epsilon_greedy(actions, epsilon, rng)
+}
+
+/// Bandit update, returns the prediction error
+pub fn update_bandit(actions: &mut Actions, action_idx: usize, reward: i32) -> f32 {
     // New estimate = old estimate + stepsize(target - old estimate)
     let stepsize = 0.1;
     let old_estimate = actions[action_idx].get_expected_value();
-    let new_estimate = old_estimate + (stepsize * (reward as f32 - old_estimate));
+    let error = reward as f32 - old_estimate;
+    let new_estimate = old_estimate + (stepsize * error);
 
     actions[action_idx].set_expected_value(new_estimate);
+    error
 }
 
 // TODO should this be inside or outside the brain? Outside seems good, but possibly better inside.
-pub fn epsilon_greedy(actions: &mut Actions, epsilon: f32) -> &mut Action {
-    if rand::thread_rng().gen::<f32>() < epsilon {
-        actions.random_action()
+pub fn epsilon_greedy<'a>(
+    actions: &'a mut Actions,
+    epsilon: f32,
+    rng: &mut impl Rng,
+) -> &'a mut Action {
+    if rng.gen::<f32>() < epsilon {
+        actions.random_action(rng)
     } else {
         actions.max_ev_action()
     }
@@ -2,18 +2,186 @@ use core::panic;
 
 use std::{collections::HashMap, fmt};
 
+use rand::Rng;
+use serde::de::{Deserializer, Error as _};
+use serde::{Deserialize, Serialize, Serializer};
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
 use crate::agent::actions::{Action, Actions};
 use crate::{Agent, CONFIG};
 
+/// How an agent chooses its actions. Set with `agent_type` in the experiment config, by name
+/// or as a table with a `type`, e.g. `{ type = "fixed", amount = 2 }`. The types after
+/// `QLEARNING` are baselines with a fixed strategy, which do not learn.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(remote = "Self", tag = "type")]
+#[allow(clippy::upper_case_acronyms)]
 pub enum AgentType {
+    #[serde(rename = "bandit", alias = "BANDIT")]
     BANDIT,
+    #[default]
+    #[serde(rename = "q_learning", alias = "QLEARNING")]
     QLEARNING,
+    /// Always take `amount`
+    #[serde(rename = "fixed")]
+    FIXED { amount: i32 },
+    /// Take an equal share of the maximum sustainable yield
+    #[serde(rename = "sustainable")]
+    SUSTAINABLE,
+    /// Take `amount` while the pool is above `threshold`, a fraction of the maximum pool,
+    /// and nothing otherwise
+    #[serde(rename = "threshold")]
+    THRESHOLD { amount: i32, threshold: f32 },
+    /// Take a uniformly random action
+    #[serde(rename = "random")]
+    RANDOM,
+    /// Always take the largest action
+    #[serde(rename = "greedy")]
+    GREEDY,
+    /// Tit-for-tat: take the mean of what the others took in the previous epoch, rounded.
+    /// Starts with the sustainable share.
+    #[serde(rename = "reciprocal")]
+    RECIPROCAL,
+    /// Take the sustainable share until another agent takes more, then the largest action
+    /// for the rest of the generation
+    #[serde(rename = "grim_trigger")]
+    GRIM,
+    /// Take what most of the others took in the previous epoch. Starts with the sustainable share.
+    #[serde(rename = "conformist")]
+    CONFORMIST,
 }
 
-#[derive(Debug, EnumIter, Clone, Copy)]
+impl AgentType {
+    pub fn learns(&self) -> bool {
+        matches!(self, AgentType::BANDIT | AgentType::QLEARNING)
+    }
+
+    /// Whether the agent starts from, or keeps to, an equal share of the sustainable yield
+    pub fn uses_sustainable_share(&self) -> bool {
+        matches!(
+            self,
+            AgentType::SUSTAINABLE
+                | AgentType::RECIPROCAL
+                | AgentType::GRIM
+                | AgentType::CONFORMIST
+        )
+    }
+}
+
+/// The amounts the living agents asked for in an epoch, published to every agent afterwards
+pub struct HarvestObservation {
+    total: i32,
+    n_agents: i32,
+    /// The largest two requests, to find the largest among the others of any agent
+    largest: [i32; 2],
+    /// Number of agents that asked for each amount
+    action_counts: Vec<i32>,
+}
+
+/// What the other agents asked for in the previous epoch, as seen by one agent
+#[derive(Clone, Copy, Debug)]
+pub struct OthersHarvest {
+    pub mean: f32,
+    pub max: i32,
+    /// The most common amount, the smallest of them on a tie
+    pub majority: i32,
+}
+
+impl HarvestObservation {
+    pub fn new(requests: &[i32], n_actions: i32) -> HarvestObservation {
+        let mut largest = [-1, -1];
+        let mut action_counts = vec![0; n_actions as usize];
+        for &request in requests {
+            if request > largest[0] {
+                largest = [request, largest[0]];
+            } else if request > largest[1] {
+                largest[1] = request;
+            }
+            action_counts[request as usize] += 1;
+        }
+        HarvestObservation {
+            total: requests.iter().sum(),
+            n_agents: requests.len() as i32,
+            largest,
+            action_counts,
+        }
+    }
+
+    /// What the others did, for an agent that asked for `own`. None without others.
+    pub fn others(&self, own: i32) -> Option<OthersHarvest> {
+        if self.n_agents < 2 {
+            return None;
+        }
+        let mut counts = self.action_counts.clone();
+        counts[own as usize] -= 1;
+        let majority = counts
+            .iter()
+            .enumerate()
+            .max_by_key(|&(action, &count)| (count, -(action as i32)))
+            .map_or(0, |(action, _)| action as i32);
+        Some(OthersHarvest {
+            mean: (self.total - own) as f32 / (self.n_agents - 1) as f32,
+            max: if own == self.largest[0] {
+                self.largest[1]
+            } else {
+                self.largest[0]
+            },
+            majority,
+        })
+    }
+}
+
+/// How the signals emitted in a signalling phase were distributed: the share of each signal
+/// of the vocabulary, split in thirds. All low when no agent signalled.
+pub fn signal_distribution(signals: &[i32], vocabulary: i32) -> Vec<ResourceState> {
+    let mut counts = vec![0; vocabulary as usize];
+    for &signal in signals {
+        counts[signal as usize] += 1;
+    }
+    counts
+        .iter()
+        .map(|&count| {
+            let share = count as f32 / signals.len().max(1) as f32;
+            if share < 1. / 3. {
+                ResourceState::LOW
+            } else if share <= 2. / 3. {
+                ResourceState::MEDIUM
+            } else {
+                ResourceState::HIGH
+            }
+        })
+        .collect()
+}
+
+impl Serialize for AgentType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        AgentType::serialize(self, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AgentType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Setting {
+            Name(String),
+            Table(toml::Value),
+        }
+
+        let table = match Setting::deserialize(deserializer)? {
+            Setting::Name(name) => {
+                let mut table = toml::map::Map::new();
+                table.insert("type".to_string(), toml::Value::String(name));
+                toml::Value::Table(table)
+            }
+            Setting::Table(table) => table,
+        };
+        AgentType::deserialize(table).map_err(D::Error::custom)
+    }
+}
+
+#[derive(Debug, EnumIter, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ResourceState {
     LOW,
     MEDIUM,
@@ -26,26 +194,39 @@ impl fmt::Display for ResourceState {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AgentState {
     commons_state: ResourceState,
     score_state: ResourceState,
+    /// The share of each signal in the last signalling phase, when communication is enabled
+    #[serde(default)]
+    signals: Option<Vec<ResourceState>>,
+    /// The mean reputation of the others, when reputations are enabled
+    #[serde(default)]
+    reputation: Option<ResourceState>,
 }
 
 impl AgentState {
     // Using state as key for the Q table, as string
     pub fn to_string(&self) -> String {
-        String::from(format!(
-            "{}_{}",
-            &self.commons_state.to_string(),
-            &self.score_state.to_string(),
-        ))
+        let mut key = format!("{}_{}", self.commons_state, self.score_state);
+        if let Some(signals) = &self.signals {
+            for (signal, share) in signals.iter().enumerate() {
+                key.push_str(&format!("_S{}{}", signal, share));
+            }
+        }
+        if let Some(reputation) = self.reputation {
+            key.push_str(&format!("_R{}", reputation));
+        }
+        key
     }
     // Maybe wierd way to initialize?
     pub fn from_values(commons_value: i32, score_value: i32) -> AgentState {
         let mut agentstate = AgentState {
             commons_state: ResourceState::MEDIUM,
             score_state: ResourceState::MEDIUM,
+            signals: None,
+            reputation: None,
         };
         agentstate.map_commons(commons_value, CONFIG.experiment.max_pool_size);
         agentstate.map_score(score_value, CONFIG.experiment.consumption);
@@ -90,9 +271,49 @@ impl AgentState {
                 vec.push(format!("{}_{}", state_1.to_string(), state_2.to_string()));
             }
         }
+        if CONFIG.communication.enabled() {
+            for signal in 0..CONFIG.communication.vocabulary {
+                vec = vec
+                    .iter()
+                    .flat_map(|key| {
+                        ResourceState::iter()
+                            .map(move |share| format!("{}_S{}{}", key, signal, share))
+                    })
+                    .collect();
+            }
+        }
+        if CONFIG.reputation.enabled {
+            vec = vec
+                .iter()
+                .flat_map(|key| {
+                    ResourceState::iter().map(move |reputation| format!("{}_R{}", key, reputation))
+                })
+                .collect();
+        }
         vec
     }
 
+    /// The same state, with the distribution of the signals heard in the signalling phase
+    pub fn with_signals(self, signals: Option<Vec<ResourceState>>) -> AgentState {
+        AgentState { signals, ..self }
+    }
+
+    /// The same state, with the others' mean reputation, which lies in [-1, 1], split in thirds
+    pub fn with_reputation(self, reputation: Option<f32>) -> AgentState {
+        AgentState {
+            reputation: reputation.map(|reputation| {
+                if reputation < -1. / 3. {
+                    ResourceState::LOW
+                } else if reputation <= 1. / 3. {
+                    ResourceState::MEDIUM
+                } else {
+                    ResourceState::HIGH
+                }
+            }),
+            ..self
+        }
+    }
+
     pub fn report(&self) {
         println!(
             "commons {} score {}",
@@ -102,17 +323,18 @@ impl AgentState {
     }
 }
 
+#[derive(Serialize, Deserialize, Clone)]
 pub struct QTable {
     pub n_actions: i32,
     pub state_action_pairs: HashMap<String, Actions>,
 }
 
 impl QTable {
-    pub fn new(n_actions: i32) -> QTable {
+    pub fn new(n_actions: i32, rng: &mut impl Rng) -> QTable {
         let mut state_action_pairs: HashMap<String, Actions> = HashMap::new();
         let state_keys = AgentState::state_keys();
         for state_key in state_keys {
-            state_action_pairs.insert(state_key, Actions::new(n_actions));
+            state_action_pairs.insert(state_key, Actions::new(n_actions, rng));
         }
         QTable {
             n_actions,
@@ -143,6 +365,23 @@ impl QTable {
         &self.get(key)[action_idx]
     }
 
+    /// The index of the action with the highest expected value, for each state in canonical order
+    pub fn greedy_policy(&self) -> Vec<usize> {
+        AgentState::state_keys()
+            .iter()
+            .map(|state| self.get(state).max_ev_action_idx())
+            .collect()
+    }
+
+    /// Take over `fraction` of the expected values of `other` in every state
+    pub fn blend(&mut self, other: &QTable, fraction: f32) {
+        for (key, actions) in self.state_action_pairs.iter_mut() {
+            if let Some(other_actions) = other.state_action_pairs.get(key) {
+                actions.blend(other_actions, fraction);
+            }
+        }
+    }
+
     pub fn report(&self) {
         for (key, value) in &self.state_action_pairs {
             println!("state: {:?}", key);
@@ -154,40 +393,51 @@ impl QTable {
         Self::average_from_vector(&agents.iter().map(|agent| &agent.brain.q_table).collect())
     }
 
+    /// Average the expected values of the given QTables. The visit counts
+    /// are summed, giving the total number of visits over all tables.
     pub fn average_from_vector(q_tables: &Vec<&QTable>) -> QTable {
         let n_actions = q_tables[0].n_actions;
-        let mut avg_q_table = QTable::new(n_actions);
-        let state_keys = &AgentState::state_keys();
-
-        // Sum the EVs from all QTables
-        for q_table in q_tables {
-            for state in state_keys {
-                for action_idx in 0..n_actions as usize {
-                    let old_value = avg_q_table
-                        .get_action(state, action_idx)
-                        .get_expected_value();
-                    let new_value =
-                        old_value + q_table.get_action(state, action_idx).get_expected_value();
-                    avg_q_table
-                        .get_action_mut(state, action_idx)
-                        .set_expected_value(new_value);
-                }
-            }
-        }
-
-        // Divide values by number of QTables to compute average EVs
+        // All initial values are overwritten, so they need not come from the experiment's rng
+        let mut avg_q_table = QTable::new(n_actions, &mut rand::thread_rng());
         let n_tables = q_tables.len() as f32;
-        for state in state_keys {
+
+        for state in &AgentState::state_keys() {
             for action_idx in 0..n_actions as usize {
-                let old_value = avg_q_table
-                    .get_action(state, action_idx)
-                    .get_expected_value();
-                avg_q_table
-                    .get_action_mut(state, action_idx)
-                    .set_expected_value(old_value / n_tables);
+                let sum_value: f32 = q_tables
+                    .iter()
+                    .map(|q_table| q_table.get_action(state, action_idx).get_expected_value())
+                    .sum();
+                let sum_visits: i32 = q_tables
+                    .iter()
+                    .map(|q_table| q_table.get_action(state, action_idx).get_times_chosen())
+                    .sum();
+                let avg_action = avg_q_table.get_action_mut(state, action_idx);
+                avg_action.set_expected_value(sum_value / n_tables);
+                avg_action.set_times_chosen(sum_visits);
             }
         }
 
         avg_q_table
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_distribution_splits_the_shares_in_thirds() {
+        use ResourceState::*;
+        assert_eq!(signal_distribution(&[0, 0, 0, 2], 3), [HIGH, LOW, LOW]);
+        assert_eq!(signal_distribution(&[0, 1, 1, 2], 3), [LOW, MEDIUM, LOW]);
+        assert_eq!(signal_distribution(&[0, 1, 2], 3), [MEDIUM, MEDIUM, MEDIUM]);
+        assert_eq!(signal_distribution(&[], 2), [LOW, LOW]);
+    }
+
+    #[test]
+    fn heard_signals_are_part_of_the_state_key() {
+        use ResourceState::*;
+        let state = AgentState::from_values(0, 0).with_signals(Some(vec![LOW, HIGH]));
+        assert_eq!(state.to_string(), "LOW_LOW_S0LOW_S1HIGH");
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use crate::statistics::{GenerationStatistics, RLStatistics};
+use crate::table::{OutputFormat, Table};
+
+/// Results sent from the experiment threads to the aggregator while the experiments run
+pub enum ExperimentMessage {
+    /// The aggregated columns of one generation of an experiment
+    Generation {
+        exp_idx: usize,
+        gen_num: i32,
+        values: Vec<f64>,
+    },
+    /// The experiment ran its last generation
+    Finished {
+        exp_idx: usize,
+        rl_stats: RLStatistics,
+    },
+}
+
+/// The sending side of the aggregation channel for a single experiment
+pub struct GenerationSender {
+    exp_idx: usize,
+    sender: Sender<ExperimentMessage>,
+}
+
+impl GenerationSender {
+    pub fn new(exp_idx: usize, sender: Sender<ExperimentMessage>) -> GenerationSender {
+        GenerationSender { exp_idx, sender }
+    }
+
+    pub fn send_generation(&self, gen_num: i32, values: Vec<f64>) {
+        self.sender
+            .send(ExperimentMessage::Generation {
+                exp_idx: self.exp_idx,
+                gen_num,
+                values,
+            })
+            .expect("Aggregator stopped receiving generation stats");
+    }
+
+    pub fn send_finished(&self, rl_stats: RLStatistics) {
+        self.sender
+            .send(ExperimentMessage::Finished {
+                exp_idx: self.exp_idx,
+                rl_stats,
+            })
+            .expect("Aggregator stopped receiving RL stats");
+    }
+}
+
+/// Running mean and sum of squared differences (Welford) of each column of a generation
+struct RunningStats {
+    count: u32,
+    mean: Vec<f64>,
+    m2: Vec<f64>,
+}
+
+impl RunningStats {
+    fn new(n_columns: usize) -> RunningStats {
+        RunningStats {
+            count: 0,
+            mean: vec![0.; n_columns],
+            m2: vec![0.; n_columns],
+        }
+    }
+
+    fn add(&mut self, values: &[f64]) {
+        self.count += 1;
+        for (idx, &value) in values.iter().enumerate() {
+            let delta = value - self.mean[idx];
+            self.mean[idx] += delta / self.count as f64;
+            self.m2[idx] += delta * (value - self.mean[idx]);
+        }
+    }
+}
+
+/// Averages the generation statistics over experiments as they come in. A generation is
+/// written to `avg_gen_stats` as soon as every experiment has either reported it or
+/// stopped, so only the generations between the slowest and fastest experiment are kept.
+pub struct StreamingAggregator {
+    table: Table,
+    n_columns: usize,
+    pending: BTreeMap<i32, RunningStats>,
+    /// The last generation reported by each experiment, -1 before the first
+    last_reported: Vec<i32>,
+    finished: Vec<bool>,
+    rl_stats: Vec<RLStatistics>,
+}
+
+impl StreamingAggregator {
+    pub fn new(
+        output_dir: &Path,
+        n_experiments: usize,
+        format: &OutputFormat,
+    ) -> Result<StreamingAggregator, Box<dyn Error>> {
+        let columns = GenerationStatistics::aggregate_header();
+        let mut header = vec!["gen_num".to_string(), "n_experiments".to_string()];
+        for column in &columns {
+            header.push(format!("{}_mean", column));
+            header.push(format!("{}_std", column));
+            header.push(format!("{}_ci95", column));
+        }
+
+        Ok(StreamingAggregator {
+            table: Table::create(output_dir, "avg_gen_stats", header, format)?,
+            n_columns: columns.len(),
+            pending: BTreeMap::new(),
+            last_reported: vec![-1; n_experiments],
+            finished: vec![false; n_experiments],
+            rl_stats: Vec::with_capacity(n_experiments),
+        })
+    }
+
+    /// Process messages until all experiments have finished, returning their RL statistics
+    pub fn run<I>(mut self, messages: I) -> Result<Vec<RLStatistics>, Box<dyn Error>>
+    where
+        I: IntoIterator<Item = ExperimentMessage>,
+    {
+        for message in messages {
+            match message {
+                ExperimentMessage::Generation {
+                    exp_idx,
+                    gen_num,
+                    values,
+                } => {
+                    let n_columns = self.n_columns;
+                    self.pending
+                        .entry(gen_num)
+                        .or_insert_with(|| RunningStats::new(n_columns))
+                        .add(&values);
+                    self.last_reported[exp_idx] = gen_num;
+                }
+                ExperimentMessage::Finished { exp_idx, rl_stats } => {
+                    self.finished[exp_idx] = true;
+                    self.rl_stats.push(rl_stats);
+                }
+            }
+            self.write_complete()?;
+        }
+        // Experiments that crashed never report that they finished; write what is left
+        let remaining: Vec<i32> = self.pending.keys().copied().collect();
+        for gen_num in remaining {
+            self.write_generation(gen_num)?;
+        }
+        self.table.finish()?;
+        Ok(self.rl_stats)
+    }
+
+    /// Write all generations that no running experiment can still report
+    fn write_complete(&mut self) -> Result<(), Box<dyn Error>> {
+        let complete_up_to = self
+            .last_reported
+            .iter()
+            .zip(&self.finished)
+            .filter(|(_, &finished)| !finished)
+            .map(|(&last_reported, _)| last_reported)
+            .min()
+            .unwrap_or(i32::MAX);
+        while let Some(&gen_num) = self.pending.keys().next() {
+            if gen_num > complete_up_to {
+                break;
+            }
+            self.write_generation(gen_num)?;
+        }
+        Ok(())
+    }
+
+    fn write_generation(&mut self, gen_num: i32) -> Result<(), Box<dyn Error>> {
+        let stats = self.pending.remove(&gen_num).unwrap();
+        let mut values = Vec::with_capacity(3 * self.n_columns);
+        for idx in 0..self.n_columns {
+            let (std, ci95) = if stats.count > 1 {
+                let std = (stats.m2[idx] / (stats.count - 1) as f64).sqrt();
+                (
+                    std,
+                    t_critical_95(stats.count - 1) * std / (stats.count as f64).sqrt(),
+                )
+            } else {
+                (0., 0.)
+            };
+            values.push(stats.mean[idx]);
+            values.push(std);
+            values.push(ci95);
+        }
+        self.table.serialize((gen_num, stats.count as i32, values))
+    }
+}
+
+/// Two-sided 95% critical value of Student's t-distribution: from the table up to 30 degrees
+/// of freedom, and interpolated in 1 / df between 30, 40, 60, 120 and the normal limit beyond
+fn t_critical_95(degrees_of_freedom: u32) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    const LARGE_DF: [(f64, f64); 5] = [
+        (30., 2.042),
+        (40., 2.021),
+        (60., 2.000),
+        (120., 1.980),
+        (f64::INFINITY, 1.960),
+    ];
+    match degrees_of_freedom {
+        0 => f64::NAN,
+        df if df as usize <= TABLE.len() => TABLE[df as usize - 1],
+        df => {
+            let df = df as f64;
+            let upper = LARGE_DF.iter().position(|&(point, _)| point >= df).unwrap();
+            let (lower_df, lower_t) = LARGE_DF[upper - 1];
+            let (upper_df, upper_t) = LARGE_DF[upper];
+            let weight = (1. / lower_df - 1. / df) / (1. / lower_df - 1. / upper_df);
+            lower_t + weight * (upper_t - lower_t)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn running_stats_match_the_two_pass_mean_and_variance() {
+        let rows = [[2., 10.], [4., 10.], [4., 10.], [5., 10.], [7., 10.]];
+        let mut stats = RunningStats::new(2);
+        for row in &rows {
+            stats.add(row);
+        }
+        assert_eq!(stats.count, 5);
+        assert!((stats.mean[0] - 4.4).abs() < 1e-12);
+        assert!((stats.mean[1] - 10.).abs() < 1e-12);
+        // Sum of squared differences from the mean
+        assert!((stats.m2[0] - 13.2).abs() < 1e-12);
+        assert!(stats.m2[1].abs() < 1e-12);
+    }
+
+    #[test]
+    fn t_critical_95_uses_the_table_and_the_normal_limit() {
+        assert!(t_critical_95(0).is_nan());
+        assert_eq!(t_critical_95(1), 12.706);
+        assert_eq!(t_critical_95(30), 2.042);
+        assert_eq!(t_critical_95(40), 2.021);
+        assert_eq!(t_critical_95(120), 1.980);
+        assert!((t_critical_95(1_000_000) - 1.96).abs() < 1e-4);
+    }
+
+    #[test]
+    fn t_critical_95_interpolates_between_the_large_df_entries() {
+        // Exact values: 2.030 at 35, 2.009 at 50 and 1.990 at 80 degrees of freedom
+        assert!((t_critical_95(35) - 2.030).abs() < 1e-3);
+        assert!((t_critical_95(50) - 2.009).abs() < 1e-3);
+        assert!((t_critical_95(80) - 1.990).abs() < 1e-3);
+        assert!(t_critical_95(31) < 2.042 && t_critical_95(31) > 2.021);
+    }
+}
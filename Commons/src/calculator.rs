@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::Serialize;
+
+use crate::commons::Commons;
+use crate::config::ExperimentConfig;
+
+/// Analytical benchmarks of a commons, the Rust successor of
+/// `calculators/calculators.py`'s `equilibrium_calculator`
+#[derive(Serialize)]
+pub struct Equilibrium {
+    pub n_agents: i32,
+    /// The largest harvest per epoch the commons can sustain indefinitely
+    pub max_sustainable_yield: i32,
+    pub sustainable_yield_per_agent: f32,
+    /// The pool left after harvesting the maximum sustainable yield
+    pub sustainable_pool: i32,
+    /// The fewest agents that can harvest the maximum sustainable yield with their largest action
+    pub min_population: Option<i32>,
+    /// The most agents the maximum sustainable yield can feed
+    pub max_population: Option<i32>,
+    pub profiles: Vec<HarvestProfile>,
+}
+
+/// The commons under a fixed harvest: every agent takes `action` resources every epoch
+#[derive(Serialize)]
+pub struct HarvestProfile {
+    pub action: i32,
+    pub total_harvest: i32,
+    /// The lowest pool at the start of an epoch once the commons settles, None if it depletes
+    pub steady_pool: Option<i32>,
+    /// The epoch in which the agents first ask for more than the pool holds
+    pub depleted_at: Option<i32>,
+    /// Whether the action covers the agents' consumption
+    pub feeds_agents: bool,
+}
+
+impl Equilibrium {
+    pub fn new(config: &ExperimentConfig) -> Equilibrium {
+        let mut commons = Commons::new(
+            config.init_pool_size,
+            config.max_pool_size,
+            config.regrowth,
+            config.regrowth_rate,
+        );
+        let max_sustainable_yield = commons.max_sustainable_yield();
+        let sustainable_pool = (0..=config.max_pool_size)
+            .find(|&pool| commons.regrown(pool) - pool == max_sustainable_yield)
+            .unwrap_or(0);
+        let largest_action = config.n_actions - 1;
+        let profiles = (0..config.n_actions)
+            .map(|action| HarvestProfile::new(&mut commons, action, config))
+            .collect();
+
+        Equilibrium {
+            n_agents: config.n_agents,
+            max_sustainable_yield,
+            sustainable_yield_per_agent: max_sustainable_yield as f32 / config.n_agents as f32,
+            sustainable_pool,
+            min_population: if largest_action > 0 {
+                Some((max_sustainable_yield + largest_action - 1) / largest_action)
+            } else {
+                None
+            },
+            max_population: if config.consumption > 0 {
+                Some(max_sustainable_yield / config.consumption)
+            } else {
+                None
+            },
+            profiles,
+        }
+    }
+}
+
+/// Each agent's equal share of the maximum sustainable yield, rounded down
+pub fn sustainable_share(config: &ExperimentConfig) -> i32 {
+    let commons = Commons::new(
+        config.init_pool_size,
+        config.max_pool_size,
+        config.regrowth,
+        config.regrowth_rate,
+    );
+    commons.max_sustainable_yield() / config.n_agents.max(1)
+}
+
+impl HarvestProfile {
+    /// Run the commons until its pool repeats or it depletes
+    fn new(commons: &mut Commons, action: i32, config: &ExperimentConfig) -> HarvestProfile {
+        commons.reset();
+        // The epoch in which each pool was first seen at the start of an epoch
+        let mut seen: HashMap<i32, i32> = HashMap::new();
+        let mut pools: Vec<i32> = Vec::new();
+        let mut depleted_at = None;
+        for epoch in 0.. {
+            if let Some(&first) = seen.get(&commons.resource_pool) {
+                pools.drain(..first as usize);
+                break;
+            }
+            seen.insert(commons.resource_pool, epoch);
+            pools.push(commons.resource_pool);
+            for _ in 0..config.n_agents {
+                commons.take_resources(action);
+            }
+            if commons.depleted {
+                depleted_at = Some(epoch);
+                break;
+            }
+            commons.grow();
+        }
+
+        HarvestProfile {
+            action,
+            total_harvest: action * config.n_agents,
+            steady_pool: match depleted_at {
+                Some(_) => None,
+                None => pools.iter().copied().min(),
+            },
+            depleted_at,
+            feeds_agents: action >= config.consumption,
+        }
+    }
+}
+
+impl fmt::Display for Equilibrium {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Maximum sustainable yield: {} per epoch, {:.2} per agent for {} agents, harvested from a pool of {}",
+            self.max_sustainable_yield,
+            self.sustainable_yield_per_agent,
+            self.n_agents,
+            self.sustainable_pool
+        )?;
+        if let Some(min_population) = self.min_population {
+            writeln!(
+                f,
+                "Smallest population to harvest it: {} agents",
+                min_population
+            )?;
+        }
+        if let Some(max_population) = self.max_population {
+            writeln!(f, "Largest population it feeds: {} agents", max_population)?;
+        }
+        writeln!(f, "\nEvery agent taking the same action each epoch:")?;
+        writeln!(
+            f,
+            "{:>8} {:>14} {:>15}  feeds agents",
+            "action", "total harvest", "steady pool"
+        )?;
+        for profile in &self.profiles {
+            let steady_pool = match (profile.steady_pool, profile.depleted_at) {
+                (Some(pool), _) => pool.to_string(),
+                (None, Some(epoch)) => format!("depleted at {}", epoch),
+                (None, None) => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:>8} {:>14} {:>15}  {}",
+                profile.action,
+                profile.total_harvest,
+                steady_pool,
+                if profile.feeds_agents { "yes" } else { "no" }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sustainable_yield_of_the_default_commons() {
+        // A pool of 100 regrows by 20 to the maximum of 120, smaller pools regrow less
+        let config = ExperimentConfig::default();
+        let equilibrium = Equilibrium::new(&config);
+        assert_eq!(equilibrium.max_sustainable_yield, 20);
+        assert_eq!(equilibrium.sustainable_pool, 100);
+        assert_eq!(equilibrium.sustainable_yield_per_agent, 2.);
+        assert_eq!(equilibrium.min_population, Some(5));
+        assert_eq!(equilibrium.max_population, Some(20));
+        assert_eq!(sustainable_share(&config), 2);
+    }
+
+    #[test]
+    fn harvests_above_the_sustainable_yield_deplete_the_commons() {
+        let equilibrium = Equilibrium::new(&ExperimentConfig::default());
+        let profile = &equilibrium.profiles[2];
+        assert_eq!(profile.total_harvest, 20);
+        // Harvested down to 100, the pool is back at 120 by the next epoch
+        assert_eq!(profile.steady_pool, Some(120));
+        assert_eq!(profile.depleted_at, None);
+
+        let profile = &equilibrium.profiles[3];
+        assert_eq!(profile.steady_pool, None);
+        assert_eq!(profile.depleted_at, Some(5));
+        assert!(profile.feeds_agents);
+        assert!(!equilibrium.profiles[0].feeds_agents);
+    }
+}
@@ -0,0 +1,71 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+use rand_pcg::Pcg64;
+use serde::{Deserialize, Serialize};
+
+use crate::agent::Agent;
+use crate::stopping::{ConvergenceMonitor, StopReason};
+
+/// Bump this whenever the layout of the checkpoint changes,
+/// so old checkpoints are rejected instead of misread
+pub const CHECKPOINT_VERSION: u32 = 3;
+
+/// Borrowed view of an experiment, used to write a checkpoint without copying the agents
+#[derive(Serialize)]
+pub struct CheckpointRef<'a> {
+    pub version: u32,
+    pub next_generation: i32,
+    pub agents: &'a Vec<Agent>,
+    pub resource_pool: i32,
+    pub depleted: bool,
+    pub rng: &'a Pcg64,
+    pub monitor: ConvergenceMonitor,
+    /// Set when the experiment stopped early, so a resumed run does not train it any further
+    pub stop_reason: Option<StopReason>,
+}
+
+/// Everything needed to continue an experiment from the start of `next_generation`
+#[derive(Deserialize)]
+pub struct Checkpoint {
+    pub version: u32,
+    pub next_generation: i32,
+    pub agents: Vec<Agent>,
+    pub resource_pool: i32,
+    pub depleted: bool,
+    pub rng: Pcg64,
+    pub monitor: ConvergenceMonitor,
+    pub stop_reason: Option<StopReason>,
+}
+
+impl<'a> CheckpointRef<'a> {
+    /// Write the checkpoint next to its destination first and then move it in place,
+    /// so a crash while writing never leaves a half written checkpoint behind
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Checkpoint, Box<dyn Error>> {
+        let checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        if checkpoint.version != CHECKPOINT_VERSION {
+            return Err(format!(
+                "Checkpoint {} has version {}, expected version {}",
+                path.display(),
+                checkpoint.version,
+                CHECKPOINT_VERSION
+            )
+            .into());
+        }
+        Ok(checkpoint)
+    }
+}
@@ -1,9 +1,11 @@
+use crate::config::{Allocation, Regrowth};
+
 /// The commons resource available to all agents in the ToTC simulation
 pub struct Commons {
     init_resources: i32,
     max_resources: i32,
     pub resource_pool: i32,
-    pub regrowth_function: fn(i32, f32) -> i32,
+    regrowth: Regrowth,
     regrowth_rate: f32,
     pub depleted: bool,
 }
@@ -12,24 +14,28 @@ impl Commons {
     pub fn new(
         resource_pool: i32,
         max_pool: i32,
-        regrowth_function: fn(i32, f32) -> i32,
+        regrowth: Regrowth,
         regrowth_rate: f32,
     ) -> Commons {
         Commons {
             init_resources: resource_pool,
             max_resources: max_pool, // TODO make better, possibly some theoretical explanation
             resource_pool,
-            regrowth_function,
+            regrowth,
             regrowth_rate,
             depleted: false,
         }
     }
 
     pub fn grow(&mut self) {
-        self.resource_pool = (self.regrowth_function)(self.resource_pool, self.regrowth_rate);
-        if self.resource_pool > self.max_resources {
-            self.resource_pool = self.max_resources;
-        }
+        self.resource_pool = self.regrown(self.resource_pool);
+    }
+
+    /// What the given pool regrows to, up to the maximum pool size
+    pub fn regrown(&self, pool: i32) -> i32 {
+        self.regrowth
+            .regrow(pool, self.regrowth_rate, self.max_resources)
+            .min(self.max_resources)
     }
 
     pub fn take_resources(&mut self, value: i32) -> i32 {
@@ -43,8 +49,77 @@ impl Commons {
         return res;
     }
 
+    /// Hand out the harvest the agents asked for, in order, by the given rule
+    pub fn allocate(&mut self, requests: &[i32], allocation: Allocation) -> Vec<i32> {
+        let total: i32 = requests.iter().sum();
+        match allocation {
+            Allocation::Proportional if total > self.resource_pool => {
+                let pool = self.resource_pool;
+                self.depleted = true;
+                requests
+                    .iter()
+                    .map(|&request| {
+                        let share = (request as i64 * pool as i64 / total as i64) as i32;
+                        self.resource_pool -= share;
+                        share
+                    })
+                    .collect()
+            }
+            _ => requests
+                .iter()
+                .map(|&request| self.take_resources(request))
+                .collect(),
+        }
+    }
+
+    /// The largest harvest per epoch that the commons can sustain indefinitely: the best
+    /// regrowth over all pool sizes left after harvesting, given the maximum pool size
+    pub fn max_sustainable_yield(&self) -> i32 {
+        (0..=self.max_resources)
+            .map(|pool| self.regrown(pool) - pool)
+            .max()
+            .unwrap_or(0)
+    }
+
     pub fn reset(&mut self) {
         self.resource_pool = self.init_resources;
         self.depleted = false;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn logistic_regrowth_slows_down_towards_the_maximum() {
+        let commons = Commons::new(100, 100, Regrowth::Logistic, 1.5);
+        assert_eq!(commons.regrown(0), 0);
+        assert_eq!(commons.regrown(50), 62);
+        assert_eq!(commons.regrown(90), 94);
+        assert_eq!(commons.regrown(100), 100);
+        assert_eq!(commons.max_sustainable_yield(), 12);
+    }
+
+    #[test]
+    fn allocation_shares_out_a_short_pool_by_the_rule() {
+        let mut commons = Commons::new(10, 10, Regrowth::Geometric, 1.2);
+        assert_eq!(
+            commons.allocate(&[4, 4, 4], Allocation::FirstCome),
+            [4, 4, 2]
+        );
+        assert!(commons.depleted);
+
+        commons.reset();
+        assert_eq!(
+            commons.allocate(&[4, 4, 4], Allocation::Proportional),
+            [3, 3, 3]
+        );
+        assert_eq!(commons.resource_pool, 1);
+        assert!(commons.depleted);
+
+        commons.reset();
+        assert_eq!(commons.allocate(&[2, 4], Allocation::Proportional), [2, 4]);
+        assert!(!commons.depleted);
+    }
+}
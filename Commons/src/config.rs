@@ -1,31 +1,580 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::fs;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
+use serde::de::{Deserializer, Error as _};
 use serde::{Deserialize, Serialize};
+use strum::IntoEnumIterator;
+use strum_macros::EnumIter;
+
+use crate::agent::structs::AgentType;
+use crate::table::{Compression, OutputFormat, TableFormat};
 
 #[derive(StructOpt)]
 #[structopt(
     name = "Tragedy of the Commons",
-    about = "A simulation of the Tragedy of the Commons using Q-learning agents."
+    about = "A simulation of the Tragedy of the Commons using Q-learning agents.",
+    setting = AppSettings::SubcommandsNegateReqs,
+    after_help = "EXIT CODES:\n    0    success\n    2    invalid configuration or options\n    3    reading or writing files failed\n    4    one or more experiments crashed"
 )]
 pub struct CommandLineArgs {
     /// Path to output csv file
-    #[structopt(parse(from_os_str))]
-    pub output_dir: std::path::PathBuf,
+    #[structopt(parse(from_os_str), required_unless_one = &["resume", "sqlite"])]
+    pub output_dir: Option<std::path::PathBuf>,
 
     /// Path to the experiment configuration file
-    #[structopt(short, long = "config_path", parse(from_os_str))]
+    #[structopt(
+        short,
+        long = "config_path",
+        parse(from_os_str),
+        conflicts_with = "resume"
+    )]
     pub config_path: Option<std::path::PathBuf>,
 
-    #[structopt(short, long, default_value = "1")]
-    pub n_experiments: i32,
+    /// Resume the interrupted run in this output directory from its last checkpoints
+    #[structopt(long, parse(from_os_str), conflicts_with = "output-dir")]
+    pub resume: Option<std::path::PathBuf>,
+
+    /// Number of experiments, overriding simulation.n_experiments of the configuration
+    #[structopt(short, long, conflicts_with = "resume")]
+    pub n_experiments: Option<i32>,
+
+    /// Override a configuration value, e.g. `--set rl_params.epsilon=0.1`. Can be repeated.
+    #[structopt(
+        long = "set",
+        value_name = "key.path=value",
+        number_of_values = 1,
+        conflicts_with = "resume"
+    )]
+    pub overrides: Vec<ConfigOverride>,
+
+    /// File format of the statistics tables: csv or parquet
+    #[structopt(long, default_value = "csv")]
+    pub format: TableFormat,
+
+    /// Compression of parquet tables: none, snappy or zstd
+    #[structopt(long, default_value = "zstd")]
+    pub compression: Compression,
+
+    /// Write the statistics tables to this SQLite database instead, which several runs can share.
+    /// The output directory is then optional, and runs are not checkpointed
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["resume", "format"])]
+    pub sqlite: Option<std::path::PathBuf>,
+
+    /// What to do when the output directory exists: ask, overwrite, fail, suffix or resume.
+    /// Defaults to ask in a terminal and to fail otherwise
+    #[structopt(long)]
+    pub on_existing: Option<OnExisting>,
+
+    /// Do not draw progress bars or print progress messages
+    #[structopt(short, long)]
+    pub quiet: bool,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt)]
+pub enum Command {
+    /// Check a configuration file for unknown keys and invalid values, without running it
+    Validate {
+        #[structopt(parse(from_os_str))]
+        config_path: PathBuf,
+    },
+    /// Compute the sustainable yield and the steady states of fixed harvests for a configuration
+    Calc {
+        /// Defaults to the default configuration, `--set` overrides apply to it
+        #[structopt(parse(from_os_str))]
+        config_path: Option<PathBuf>,
+
+        /// Print the results as JSON
+        #[structopt(long)]
+        json: bool,
+    },
+}
+
+impl CommandLineArgs {
+    pub fn output_format(&self) -> OutputFormat {
+        OutputFormat::new(self.format, self.compression)
+    }
+
+    /// Batch jobs have no terminal to answer the overwrite prompt in, so they fail instead
+    pub fn on_existing(&self) -> OnExisting {
+        match self.on_existing {
+            Some(on_existing) => on_existing,
+            None if std::io::stdin().is_terminal() => OnExisting::Ask,
+            None => OnExisting::Fail,
+        }
+    }
+
+    /// The `--set` overrides, followed by the options that override a single configuration value
+    pub fn config_overrides(&self) -> Vec<ConfigOverride> {
+        let mut overrides = self.overrides.clone();
+        if let Some(n_experiments) = self.n_experiments {
+            overrides.push(ConfigOverride {
+                key: "simulation.n_experiments".to_string(),
+                value: toml::Value::Integer(n_experiments as i64),
+            });
+        }
+        overrides
+    }
+
+    /// The run directory to continue: given with `--resume`, or an existing output
+    /// directory of an earlier run with `--on-existing resume`
+    pub fn resume_dir(&self) -> Option<PathBuf> {
+        match (&self.resume, &self.output_dir) {
+            (Some(resume_dir), _) => Some(resume_dir.clone()),
+            (None, Some(output_dir))
+                if self.on_existing() == OnExisting::Resume
+                    && output_dir.join("experiment.toml").exists() =>
+            {
+                Some(output_dir.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What to do with an output directory that already exists
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OnExisting {
+    /// Ask whether to remove its contents
+    Ask,
+    Overwrite,
+    Fail,
+    /// Write to the first free `<dir>_1`, `<dir>_2`, ... instead
+    Suffix,
+    /// Continue the run in it from its last checkpoints
+    Resume,
+}
+
+impl FromStr for OnExisting {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ask" => Ok(OnExisting::Ask),
+            "overwrite" => Ok(OnExisting::Overwrite),
+            "fail" => Ok(OnExisting::Fail),
+            "suffix" => Ok(OnExisting::Suffix),
+            "resume" => Ok(OnExisting::Resume),
+            _ => Err(format!(
+                "Unknown value {}, expected ask, overwrite, fail, suffix or resume",
+                s
+            )),
+        }
+    }
+}
+
+/// A `key.path=value` override of a configuration value from the command line
+#[derive(Clone, Debug)]
+pub struct ConfigOverride {
+    pub key: String,
+    pub value: toml::Value,
+}
+
+impl FromStr for ConfigOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected key.path=value, got {}", s))?;
+        // A value that is not valid TOML, like an unquoted name, is taken as a string
+        let value = format!("value = {}", value)
+            .parse::<toml::Value>()
+            .ok()
+            .and_then(|table| table.get("value").cloned())
+            .unwrap_or_else(|| toml::Value::String(value.trim().to_string()));
+        Ok(ConfigOverride {
+            key: key.trim().to_string(),
+            value,
+        })
+    }
+}
+
+impl ConfigOverride {
+    fn apply(&self, config: &mut toml::Value) -> Result<(), ConfigIssue> {
+        let not_a_table = || ConfigIssue::new(&self.key, "is not inside a table".to_string());
+        let mut segments: Vec<&str> = self.key.split('.').collect();
+        let name = segments.pop().unwrap();
+        let mut table = config;
+        for segment in segments {
+            table = table
+                .as_table_mut()
+                .ok_or_else(not_a_table)?
+                .entry(segment.to_string())
+                .or_insert_with(|| toml::Value::Table(Default::default()));
+        }
+        table
+            .as_table_mut()
+            .ok_or_else(not_a_table)?
+            .insert(name.to_string(), self.value.clone());
+        Ok(())
+    }
+}
+
+/// A problem in a configuration file, with the TOML key path it concerns
+pub struct ConfigIssue {
+    pub key: String,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    fn new(key: &str, message: String) -> ConfigIssue {
+        ConfigIssue {
+            key: key.to_string(),
+            message,
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize, Default, Copy, Clone)]
+impl AgentType {
+    /// Check that the baseline's parameters fit the experiment
+    fn validate(&self, key: &str, exp: &ExperimentConfig) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        if let AgentType::FIXED { amount } | AgentType::THRESHOLD { amount, .. } = *self {
+            if !(0..exp.n_actions).contains(&amount) {
+                issues.push(ConfigIssue::new(
+                    &format!("{}.amount", key),
+                    format!(
+                        "must be one of the actions 0 to {}, got {}",
+                        exp.n_actions - 1,
+                        amount
+                    ),
+                ));
+            }
+        }
+        if let AgentType::THRESHOLD { threshold, .. } = *self {
+            if !(0.0..=1.0).contains(&threshold) {
+                issues.push(ConfigIssue::new(
+                    &format!("{}.threshold", key),
+                    format!("must be a fraction of the maximum pool, got {}", threshold),
+                ));
+            }
+        }
+        issues
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.key, self.message)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone)]
 pub struct Config {
     pub experiment: ExperimentConfig,
     pub rl_params: RLParameters,
     pub state_thresholds: StateThresholds,
     pub simulation: SimulationConfig,
+    #[serde(default)]
+    pub evaluation: EvaluationConfig,
+    #[serde(default)]
+    pub output: OutputConfig,
+    #[serde(default)]
+    pub stopping: StoppingConfig,
+    #[serde(default)]
+    pub communication: CommunicationConfig,
+    #[serde(default)]
+    pub reputation: ReputationConfig,
+    #[serde(default)]
+    pub voting: VotingConfig,
+    #[serde(default)]
+    pub ageing: AgeingConfig,
+    /// Agents of other types than `experiment.agent_type`, e.g. baselines among learners.
+    /// An empty list is left out, as toml cannot write it after the tables above.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub population: Vec<AgentGroup>,
+}
+
+/// `count` of the agents are of `agent_type`
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct AgentGroup {
+    pub count: i32,
+    pub agent_type: AgentType,
+}
+
+impl Config {
+    /// Read a configuration file, or start from the defaults without one, and apply the
+    /// overrides to it. Reports every unknown key and invalid value.
+    pub fn load(
+        path: Option<&Path>,
+        overrides: &[ConfigOverride],
+    ) -> Result<Config, Vec<ConfigIssue>> {
+        let mut text = match path {
+            Some(path) => fs::read_to_string(path).map_err(|e| {
+                vec![ConfigIssue::new(
+                    "",
+                    format!("Could not read {}: {}", path.display(), e),
+                )]
+            })?,
+            None => toml::to_string(&Config::default()).unwrap(),
+        };
+        if !overrides.is_empty() {
+            // Go back to text, so errors keep naming the key they are about
+            let mut config: toml::Value = text
+                .parse()
+                .map_err(|e: toml::de::Error| vec![ConfigIssue::new("", e.to_string())])?;
+            let issues: Vec<ConfigIssue> = overrides
+                .iter()
+                .filter_map(|o| o.apply(&mut config).err())
+                .collect();
+            if !issues.is_empty() {
+                return Err(issues);
+            }
+            text = toml::to_string(&config).unwrap();
+        }
+        let mut unknown_keys = Vec::new();
+        let parsed: Result<Config, _> =
+            serde_ignored::deserialize(&mut toml::Deserializer::new(&text), |key| {
+                unknown_keys.push(key.to_string())
+            });
+        let mut issues: Vec<ConfigIssue> = unknown_keys
+            .iter()
+            .map(|key| ConfigIssue::new(key, "unknown key".to_string()))
+            .collect();
+        match parsed {
+            Ok(config) => {
+                issues.extend(config.validate());
+                if issues.is_empty() {
+                    Ok(config)
+                } else {
+                    Err(issues)
+                }
+            }
+            Err(e) => {
+                issues.push(ConfigIssue::new("", e.to_string()));
+                Err(issues)
+            }
+        }
+    }
+
+    /// The type of every agent: the `population` groups in order, and then as many agents of
+    /// `experiment.agent_type` as needed to make `n_agents`
+    pub fn agent_types(&self) -> Vec<AgentType> {
+        let n_agents = self.experiment.n_agents.max(0) as usize;
+        let mut agent_types: Vec<AgentType> = self
+            .population
+            .iter()
+            .flat_map(|group| std::iter::repeat_n(group.agent_type, group.count.max(0) as usize))
+            .collect();
+        agent_types.truncate(n_agents);
+        agent_types.resize(n_agents, self.experiment.agent_type);
+        agent_types
+    }
+
+    /// Check the values that parse, but that the simulation cannot run with
+    pub fn validate(&self) -> Vec<ConfigIssue> {
+        let mut issues = Vec::new();
+        let exp = &self.experiment;
+        for (key, value) in [
+            ("experiment.n_generations", exp.n_generations),
+            ("experiment.epochs_per_gen", exp.epochs_per_gen),
+            ("experiment.n_agents", exp.n_agents),
+            ("experiment.n_actions", exp.n_actions),
+            ("simulation.n_experiments", self.simulation.n_experiments),
+        ] {
+            if value <= 0 {
+                issues.push(ConfigIssue::new(
+                    key,
+                    format!("must be positive, got {}", value),
+                ));
+            }
+        }
+        // Intervals and counts where 0 disables the output or phase
+        let output = &self.output;
+        for (key, value) in [
+            (
+                "simulation.checkpoint_interval",
+                self.simulation.checkpoint_interval,
+            ),
+            ("evaluation.interval", self.evaluation.interval),
+            ("output.epoch_trace_interval", output.epoch_trace_interval),
+            ("output.epoch_trace_last", output.epoch_trace_last),
+            (
+                "output.qtable_snapshot_interval",
+                output.qtable_snapshot_interval,
+            ),
+        ] {
+            if value < 0 {
+                issues.push(ConfigIssue::new(
+                    key,
+                    format!("must not be negative, got {}", value),
+                ));
+            }
+        }
+        if self.evaluation.enabled() && self.evaluation.n_generations <= 0 {
+            issues.push(ConfigIssue::new(
+                "evaluation.n_generations",
+                format!(
+                    "must be positive when evaluating, got {}",
+                    self.evaluation.n_generations
+                ),
+            ));
+        }
+        issues.extend(exp.agent_type.validate("experiment.agent_type", exp));
+        for (idx, group) in self.population.iter().enumerate() {
+            issues.extend(
+                group
+                    .agent_type
+                    .validate(&format!("population[{}].agent_type", idx), exp),
+            );
+            if group.count < 0 {
+                issues.push(ConfigIssue::new(
+                    &format!("population[{}].count", idx),
+                    format!("must not be negative, got {}", group.count),
+                ));
+            }
+        }
+        let grouped: i32 = self.population.iter().map(|group| group.count).sum();
+        if exp.n_agents > 0 && grouped > exp.n_agents {
+            issues.push(ConfigIssue::new(
+                "population",
+                format!(
+                    "the groups hold {} agents, more than n_agents ({})",
+                    grouped, exp.n_agents
+                ),
+            ));
+        }
+        // Action i takes i resources from the pool
+        if exp.n_actions - 1 > exp.max_pool_size {
+            issues.push(ConfigIssue::new(
+                "experiment.n_actions",
+                format!(
+                    "the largest of {} actions takes {} resources, more than max_pool_size ({})",
+                    exp.n_actions,
+                    exp.n_actions - 1,
+                    exp.max_pool_size
+                ),
+            ));
+        }
+        if exp.consumption < 0 {
+            issues.push(ConfigIssue::new(
+                "experiment.consumption",
+                format!("must not be negative, got {}", exp.consumption),
+            ));
+        }
+        if exp.regrowth_rate <= 0. {
+            issues.push(ConfigIssue::new(
+                "experiment.regrowth_rate",
+                format!("must be positive, got {}", exp.regrowth_rate),
+            ));
+        }
+        let thresholds = &self.state_thresholds;
+        if thresholds.commons_low >= thresholds.commons_med {
+            issues.push(ConfigIssue::new(
+                "state_thresholds.commons_low",
+                format!(
+                    "must be below commons_med ({}), got {}",
+                    thresholds.commons_med, thresholds.commons_low
+                ),
+            ));
+        }
+        if self.communication.vocabulary < 0 || self.communication.vocabulary > MAX_VOCABULARY {
+            issues.push(ConfigIssue::new(
+                "communication.vocabulary",
+                format!(
+                    "must be between 0 and {}, as every signal triples the states, got {}",
+                    MAX_VOCABULARY, self.communication.vocabulary
+                ),
+            ));
+        }
+        let reputation = &self.reputation;
+        if reputation.rate <= 0. || reputation.rate > 1. {
+            issues.push(ConfigIssue::new(
+                "reputation.rate",
+                format!("must be in (0, 1], got {}", reputation.rate),
+            ));
+        }
+        if let Some(norm) = reputation.norm {
+            if norm < 0 {
+                issues.push(ConfigIssue::new(
+                    "reputation.norm",
+                    format!("must not be negative, got {}", norm),
+                ));
+            }
+        }
+        if reputation.priority && !reputation.enabled {
+            issues.push(ConfigIssue::new(
+                "reputation.priority",
+                "needs reputation.enabled".to_string(),
+            ));
+        }
+        let voting = &self.voting;
+        for (idx, &quota) in voting.quotas.iter().enumerate() {
+            if quota < 0 {
+                issues.push(ConfigIssue::new(
+                    &format!("voting.quotas[{}]", idx),
+                    format!("must not be negative, got {}", quota),
+                ));
+            } else if voting.quotas[..idx].contains(&quota) {
+                issues.push(ConfigIssue::new(
+                    &format!("voting.quotas[{}]", idx),
+                    format!("{} is on the ballot already", quota),
+                ));
+            }
+        }
+        if voting.interval < 0 {
+            issues.push(ConfigIssue::new(
+                "voting.interval",
+                format!("must not be negative, got {}", voting.interval),
+            ));
+        }
+        let ageing = &self.ageing;
+        if ageing.max_age < 0 {
+            issues.push(ConfigIssue::new(
+                "ageing.max_age",
+                format!("must not be negative, got {}", ageing.max_age),
+            ));
+        }
+        if ageing.consumption_increase < 0. {
+            issues.push(ConfigIssue::new(
+                "ageing.consumption_increase",
+                format!("must not be negative, got {}", ageing.consumption_increase),
+            ));
+        }
+        for (key, fraction) in [
+            ("ageing.capacity_decline", ageing.capacity_decline),
+            ("ageing.inherit_values", ageing.inherit_values),
+            ("ageing.inherit_score", ageing.inherit_score),
+        ] {
+            if !(0. ..=1.).contains(&fraction) {
+                issues.push(ConfigIssue::new(
+                    key,
+                    format!("must be in [0, 1], got {}", fraction),
+                ));
+            }
+        }
+        if ageing.replace && !ageing.enabled() {
+            issues.push(ConfigIssue::new(
+                "ageing.replace",
+                "needs a positive ageing.max_age".to_string(),
+            ));
+        }
+        if let InitMode::Random { scale } | InitMode::Optimistic { scale, .. } =
+            self.rl_params.init_mode
+        {
+            if scale < 0. {
+                issues.push(ConfigIssue::new(
+                    "rl_params.init_mode.scale",
+                    format!("must not be negative, got {}", scale),
+                ));
+            }
+        }
+        issues
+    }
 }
 
 #[derive(Serialize, Deserialize, Copy, Clone)]
@@ -39,6 +588,12 @@ pub struct ExperimentConfig {
     pub regrowth_rate: f32,
     pub consumption: i32,
     pub empty_commons_purge: bool,
+    #[serde(default, deserialize_with = "name_or_code")]
+    pub regrowth: Regrowth,
+    #[serde(default, deserialize_with = "name_or_code")]
+    pub allocation: Allocation,
+    #[serde(default)]
+    pub agent_type: AgentType,
 }
 
 impl Default for ExperimentConfig {
@@ -53,17 +608,97 @@ impl Default for ExperimentConfig {
             regrowth_rate: 1.2,
             consumption: 1,
             empty_commons_purge: false,
+            regrowth: Regrowth::Geometric,
+            allocation: Allocation::FirstCome,
+            agent_type: AgentType::QLEARNING,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Copy, Clone)]
+/// How the pool regrows after the harvest, at `regrowth_rate`, up to the maximum pool size
+#[derive(Serialize, Deserialize, EnumIter, Copy, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Regrowth {
+    /// The pool is multiplied by the rate
+    #[default]
+    Geometric,
+    /// The pool grows by `rate - 1` of itself, slowing down towards the maximum pool size
+    Logistic,
+}
+
+impl Regrowth {
+    /// The pool after regrowing, before it is capped at the maximum pool size
+    pub fn regrow(self, pool: i32, rate: f32, max_pool: i32) -> i32 {
+        match self {
+            Regrowth::Geometric => (pool as f32 * rate) as i32,
+            Regrowth::Logistic => {
+                let growth = (rate - 1.) * pool as f32 * (1. - pool as f32 / max_pool as f32);
+                pool + growth as i32
+            }
+        }
+    }
+}
+
+/// How the harvest is shared out when the agents ask for more than the pool holds
+#[derive(Serialize, Deserialize, EnumIter, Copy, Clone, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Allocation {
+    /// The agents take in turn until the pool is empty
+    #[default]
+    FirstCome,
+    /// Every agent gets the same fraction of what it asked for, rounded down
+    Proportional,
+}
+
+/// Accept integer codes next to the names of settings without fields, where the code is
+/// the position of the variant, e.g. `allocation = 1` for `allocation = "proportional"`
+fn name_or_code<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Serialize + Deserialize<'de> + IntoEnumIterator,
+    T::Iterator: Iterator<Item = T>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Code(i64),
+        Name(String),
+    }
+
+    match Setting::deserialize(deserializer)? {
+        Setting::Code(code) => usize::try_from(code)
+            .ok()
+            .and_then(|code| T::iter().nth(code))
+            .ok_or_else(|| {
+                D::Error::custom(format!(
+                    "unknown code {}, expected one of {}",
+                    code,
+                    T::iter()
+                        .enumerate()
+                        .map(|(code, variant)| {
+                            format!("{} ({})", code, toml::Value::try_from(variant).unwrap())
+                        })
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ))
+            }),
+        Setting::Name(name) => T::deserialize(toml::Value::String(name)).map_err(D::Error::custom),
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct RLParameters {
     pub epsilon: f32,
     pub alpha: f32,
     pub gamma: f32,
     pub death_punish: i32,
-    pub init_mode: i32,
+    #[serde(default)]
+    pub q_table_path: Option<String>,
+    #[serde(default)]
+    pub freeze_learning: bool,
+    // Tables come after the plain values, or the config cannot be written as TOML
+    #[serde(deserialize_with = "init_mode_or_code")]
+    pub init_mode: InitMode,
 }
 
 impl Default for RLParameters {
@@ -73,11 +708,83 @@ impl Default for RLParameters {
             alpha: 0.05,
             gamma: 0.9,
             death_punish: 100000,
-            init_mode: 0, // 0: random, 1: optimistic initial values, 2: 0.0
+            q_table_path: None, // q_tables.json of an earlier run, replaces init_mode when set
+            freeze_learning: false,
+            init_mode: InitMode::Random {
+                scale: DEFAULT_INIT_SCALE,
+            },
         }
     }
 }
 
+impl RLParameters {
+    /// The q_tables.json of an earlier run to start the agents from, if any
+    pub fn model_path(&self) -> Option<&str> {
+        match &self.init_mode {
+            InitMode::FromFile { path } => Some(path),
+            _ => self.q_table_path.as_deref(),
+        }
+    }
+}
+
+const DEFAULT_INIT_SCALE: f32 = 0.01;
+
+fn default_init_scale() -> f32 {
+    DEFAULT_INIT_SCALE
+}
+
+/// How the expected values of a new agent's actions are initialised, e.g.
+/// `init_mode = { type = "optimistic", value = 5.0 }`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum InitMode {
+    /// Uniform noise in [0, scale)
+    Random {
+        #[serde(default = "default_init_scale")]
+        scale: f32,
+    },
+    /// `value` plus uniform noise in [0, scale)
+    Optimistic {
+        value: f32,
+        #[serde(default = "default_init_scale")]
+        scale: f32,
+    },
+    Constant {
+        value: f32,
+    },
+    /// The Q-tables in the q_tables.json of an earlier run
+    FromFile {
+        path: String,
+    },
+}
+
+/// Accept the integer codes of older configurations next to the tagged form:
+/// 0 random, 1 optimistic initial values of 5.0 and 2 a constant 0.0
+fn init_mode_or_code<'de, D: Deserializer<'de>>(deserializer: D) -> Result<InitMode, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Setting {
+        Code(i64),
+        Tagged(toml::Value),
+    }
+
+    match Setting::deserialize(deserializer)? {
+        Setting::Code(0) => Ok(InitMode::Random {
+            scale: DEFAULT_INIT_SCALE,
+        }),
+        Setting::Code(1) => Ok(InitMode::Optimistic {
+            value: 5.0,
+            scale: DEFAULT_INIT_SCALE,
+        }),
+        Setting::Code(2) => Ok(InitMode::Constant { value: 0.0 }),
+        Setting::Code(code) => Err(D::Error::custom(format!(
+            "unknown init_mode {}, expected 0 (random), 1 (optimistic), 2 (constant) or a table with a type",
+            code
+        ))),
+        Setting::Tagged(value) => value.try_into().map_err(D::Error::custom),
+    }
+}
+
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct StateThresholds {
     pub commons_low: f32,
@@ -100,10 +807,461 @@ impl Default for StateThresholds {
 #[derive(Serialize, Deserialize, Copy, Clone)]
 pub struct SimulationConfig {
     pub n_experiments: i32,
+    #[serde(default = "default_checkpoint_interval")]
+    pub checkpoint_interval: i32,
+    /// Experiment i is seeded with `seed + i`; without a seed every experiment gets a random one
+    #[serde(default)]
+    pub seed: Option<u64>,
 }
 
 impl Default for SimulationConfig {
     fn default() -> Self {
-        Self { n_experiments: 1 }
+        Self {
+            n_experiments: 1,
+            checkpoint_interval: default_checkpoint_interval(),
+            seed: None,
+        }
+    }
+}
+
+/// In generations, 0 disables checkpointing
+fn default_checkpoint_interval() -> i32 {
+    10000
+}
+
+impl SimulationConfig {
+    pub fn experiment_seed(&self, exp_idx: i32) -> u64 {
+        match self.seed {
+            Some(seed) => seed + exp_idx as u64,
+            None => rand::random::<u32>() as u64,
+        }
+    }
+}
+
+/// Evaluation phases, in which agents act greedily without learning
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct EvaluationConfig {
+    pub interval: i32,
+    pub n_generations: i32,
+    pub epsilon: f32,
+    pub at_end: bool,
+}
+
+impl Default for EvaluationConfig {
+    fn default() -> Self {
+        Self {
+            interval: 0, // generations between evaluations, 0 disables periodic evaluation
+            n_generations: 10,
+            epsilon: 0.0,
+            at_end: false,
+        }
+    }
+}
+
+impl EvaluationConfig {
+    pub fn enabled(&self) -> bool {
+        self.interval > 0 || self.at_end
+    }
+}
+
+/// Optional statistics output, next to the default gen_stats.csv and rl_stats.csv
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+#[serde(default)]
+pub struct OutputConfig {
+    pub agent_stats: bool,
+    pub epoch_trace_interval: i32, // trace every nth generation, 0 disables
+    pub epoch_trace_last: i32,     // trace the last k generations
+    pub rl_stats_long: bool,
+    pub learning_diagnostics: bool,
+    pub qtable_snapshot_interval: i32, // generations between Q-table snapshots, 0 disables
+    pub qtable_snapshot_per_agent: bool,
+}
+
+impl OutputConfig {
+    pub fn epoch_trace_enabled(&self) -> bool {
+        self.epoch_trace_interval > 0 || self.epoch_trace_last > 0
+    }
+
+    /// Whether the epochs of this generation are written to the epoch trace
+    pub fn traces_generation(&self, generation_number: i32, n_generations: i32) -> bool {
+        (self.epoch_trace_interval > 0 && generation_number % self.epoch_trace_interval == 0)
+            || generation_number >= n_generations - self.epoch_trace_last
+    }
+}
+
+/// Criteria to stop an experiment before `n_generations`
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct StoppingConfig {
+    pub window: i32, // generations, 0 disables the convergence criteria
+    pub metric: Option<StoppingMetric>,
+    pub tolerance: f32,
+    pub policy_stable: bool,
+    pub max_wall_seconds: u64, // 0 disables the wall-clock budget
+}
+
+impl Default for StoppingConfig {
+    fn default() -> Self {
+        Self {
+            window: 0,
+            metric: None,
+            tolerance: 0.5, // allowed range of the moving average over the window
+            policy_stable: false,
+            max_wall_seconds: 0,
+        }
+    }
+}
+
+/// Largest vocabulary, as the agents see the share of every signal in their state
+pub const MAX_VOCABULARY: i32 = 5;
+
+/// Cheap talk: before harvesting, every agent emits one of `vocabulary` signals
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+#[serde(default)]
+pub struct CommunicationConfig {
+    pub vocabulary: i32, // number of distinct signals, 0 disables the signalling phase
+}
+
+impl CommunicationConfig {
+    pub fn enabled(&self) -> bool {
+        self.vocabulary > 0
+    }
+}
+
+/// Reputations: the agents judge every harvest against a norm, and see the others' reputations
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct ReputationConfig {
+    pub enabled: bool,
+    /// The largest harvest that counts as fair, by default each agent's sustainable share
+    pub norm: Option<i32>,
+    pub rate: f32,      // weight of the latest harvest in the reputation
+    pub priority: bool, // agents with better reputations take from the commons first
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            norm: None,
+            rate: 0.1,
+            priority: false,
+        }
+    }
+}
+
+/// Self-governance: the agents vote on a quota, the most any of them may take in an epoch
+#[derive(Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct VotingConfig {
+    pub quotas: Vec<i32>, // the options on the ballot, empty disables voting
+    pub interval: i32,    // epochs between votes, 0 votes only at the start of each generation
+    pub rule: VotingRule,
+}
+
+impl VotingConfig {
+    pub fn enabled(&self) -> bool {
+        !self.quotas.is_empty()
+    }
+
+    /// Whether the agents vote before this epoch
+    pub fn votes_in(&self, epoch_number: i32) -> bool {
+        epoch_number == 0 || (self.interval > 0 && epoch_number % self.interval == 0)
+    }
+}
+
+/// Ageing: agents need more and can take less as they grow old, and die at `max_age`
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+#[serde(default)]
+pub struct AgeingConfig {
+    pub max_age: i32,              // epochs an agent lives at most, 0 disables ageing
+    pub consumption_increase: f32, // fraction of the consumption added by max_age
+    pub capacity_decline: f32,     // fraction of the largest harvest lost by max_age
+    pub replace: bool,             // newcomers take the place of agents that die of old age
+    pub inherit_values: f32,       // fraction of the parent's learned values a newcomer inherits
+    pub inherit_score: f32,        // fraction of the parent's score a newcomer inherits
+}
+
+impl AgeingConfig {
+    pub fn enabled(&self) -> bool {
+        self.max_age > 0
+    }
+
+    /// How far an agent of `age` is on its way to `max_age`, from 0 to 1
+    fn age_fraction(&self, age: i32) -> f32 {
+        if self.enabled() {
+            (age as f32 / self.max_age as f32).min(1.)
+        } else {
+            0.
+        }
+    }
+
+    /// What an agent of `age` consumes each epoch
+    pub fn consumption(&self, consumption: i32, age: i32) -> i32 {
+        (consumption as f32 * (1. + self.consumption_increase * self.age_fraction(age))).round()
+            as i32
+    }
+
+    /// The most an agent of `age` can take from the commons in an epoch
+    pub fn capacity(&self, largest_action: i32, age: i32) -> i32 {
+        (largest_action as f32 * (1. - self.capacity_decline * self.age_fraction(age))).round()
+            as i32
+    }
+}
+
+/// How the ballots decide the quota
+#[derive(Serialize, Deserialize, Copy, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum VotingRule {
+    /// The quota with the most votes, the first of them in `quotas` on a tie
+    #[default]
+    Majority,
+    /// The median of the quotas voted for, the lower one for an even number of votes
+    Median,
+}
+
+impl VotingRule {
+    /// The index in `quotas` of the winning quota, None without ballots
+    pub fn winner(&self, ballots: &[usize], quotas: &[i32]) -> Option<usize> {
+        if ballots.is_empty() {
+            return None;
+        }
+        match self {
+            VotingRule::Majority => {
+                let mut counts = vec![0; quotas.len()];
+                for &option in ballots {
+                    counts[option] += 1;
+                }
+                counts
+                    .iter()
+                    .enumerate()
+                    .max_by_key(|&(option, &count)| (count, -(option as i32)))
+                    .map(|(option, _)| option)
+            }
+            VotingRule::Median => {
+                let mut voted: Vec<usize> = ballots.to_vec();
+                voted.sort_by_key(|&option| quotas[option]);
+                Some(voted[(voted.len() - 1) / 2])
+            }
+        }
+    }
+}
+
+/// The generation statistic whose moving average is watched for convergence
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum StoppingMetric {
+    EpochsRan,
+    AgentsAlive,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn issue_keys(issues: &[ConfigIssue]) -> Vec<&str> {
+        issues.iter().map(|issue| issue.key.as_str()).collect()
+    }
+
+    /// Load the default configuration with the given command line options
+    fn load_with(options: &[&str]) -> Result<Config, Vec<ConfigIssue>> {
+        let args = CommandLineArgs::from_iter(["commons", "out"].iter().chain(options));
+        Config::load(None, &args.config_overrides())
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert!(Config::default().validate().is_empty());
+    }
+
+    #[test]
+    fn validate_reports_counts_and_intervals_by_key() {
+        let mut config = Config::default();
+        config.experiment.n_actions = 0;
+        config.experiment.epochs_per_gen = -1;
+        config.evaluation.interval = -5;
+        config.evaluation.n_generations = 0;
+        config.output.qtable_snapshot_interval = -1;
+        let issues = config.validate();
+        assert_eq!(
+            issue_keys(&issues),
+            [
+                "experiment.epochs_per_gen",
+                "experiment.n_actions",
+                "evaluation.interval",
+                "output.qtable_snapshot_interval",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_reports_thresholds_out_of_order() {
+        let mut config = Config::default();
+        config.state_thresholds.commons_low = 0.8;
+        assert_eq!(
+            issue_keys(&config.validate()),
+            ["state_thresholds.commons_low"]
+        );
+    }
+
+    #[test]
+    fn validate_bounds_the_vocabulary() {
+        let mut config = Config::default();
+        config.communication.vocabulary = MAX_VOCABULARY;
+        assert!(config.validate().is_empty());
+        config.communication.vocabulary = MAX_VOCABULARY + 1;
+        assert_eq!(issue_keys(&config.validate()), ["communication.vocabulary"]);
+    }
+
+    #[test]
+    fn load_reports_missing_and_unknown_keys() {
+        let mut config = toml::Value::try_from(Config::default()).unwrap();
+        let rl_params = config["rl_params"].as_table_mut().unwrap();
+        rl_params.remove("epsilon");
+        rl_params.insert("epsilonn".to_string(), toml::Value::Float(0.1));
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("experiment.toml");
+        fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+
+        let issues = match Config::load(Some(&path), &[]) {
+            Ok(_) => panic!("a configuration without rl_params.epsilon loaded"),
+            Err(issues) => issues,
+        };
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0].key, "rl_params.epsilonn");
+        assert!(issues[1].message.contains("missing field `epsilon`"));
+    }
+
+    #[test]
+    fn overrides_replace_values_of_the_defaults() {
+        let config = match load_with(&[
+            "--set",
+            "rl_params.alpha=0.5",
+            "--set",
+            "experiment.agent_type=bandit",
+            "-n",
+            "4",
+        ]) {
+            Ok(config) => config,
+            Err(_) => panic!("the overrides were rejected"),
+        };
+        assert_eq!(config.rl_params.alpha, 0.5);
+        assert_eq!(config.experiment.agent_type, AgentType::BANDIT);
+        assert_eq!(config.simulation.n_experiments, 4);
+    }
+
+    #[test]
+    fn overrides_report_unknown_keys_and_invalid_values() {
+        let issues = match load_with(&["--set", "rl_params.alhpa=0.5"]) {
+            Ok(_) => panic!("an override of an unknown key was accepted"),
+            Err(issues) => issues,
+        };
+        assert_eq!(issue_keys(&issues), ["rl_params.alhpa"]);
+
+        let issues = match load_with(&["--set", "experiment.n_agents=0"]) {
+            Ok(_) => panic!("an override without agents was accepted"),
+            Err(issues) => issues,
+        };
+        assert_eq!(issue_keys(&issues), ["experiment.n_agents"]);
+    }
+
+    #[test]
+    fn override_needs_a_key_and_a_value() {
+        assert!("rl_params.alpha".parse::<ConfigOverride>().is_err());
+        let unquoted = "experiment.agent_type=q_learning"
+            .parse::<ConfigOverride>()
+            .unwrap();
+        assert_eq!(
+            unquoted.value,
+            toml::Value::String("q_learning".to_string())
+        );
+    }
+
+    fn init_mode(setting: &str) -> Result<InitMode, Vec<ConfigIssue>> {
+        load_with(&["--set", &format!("rl_params.init_mode={}", setting)])
+            .map(|config| config.rl_params.init_mode)
+    }
+
+    #[test]
+    fn init_mode_accepts_the_legacy_codes() {
+        assert!(matches!(
+            init_mode("0"),
+            Ok(InitMode::Random { scale }) if scale == DEFAULT_INIT_SCALE
+        ));
+        assert!(matches!(
+            init_mode("1"),
+            Ok(InitMode::Optimistic { value, .. }) if value == 5.0
+        ));
+        assert!(matches!(
+            init_mode("2"),
+            Ok(InitMode::Constant { value }) if value == 0.0
+        ));
+        assert!(init_mode("3").is_err());
+    }
+
+    #[test]
+    fn init_mode_accepts_tables_with_a_type() {
+        assert!(matches!(
+            init_mode("{ type = \"optimistic\", value = 2.0 }"),
+            Ok(InitMode::Optimistic { value, scale }) if value == 2.0 && scale == DEFAULT_INIT_SCALE
+        ));
+        assert!(matches!(
+            init_mode("{ type = \"from_file\", path = \"q_tables.json\" }"),
+            Ok(InitMode::FromFile { path }) if path == "q_tables.json"
+        ));
+        assert!(init_mode("{ type = \"constant\", value = 1.0, scale = 0.1 }").is_err());
+    }
+
+    #[test]
+    fn regrowth_and_allocation_accept_names_and_codes() {
+        let config = load_with(&["--set", "experiment.regrowth=1"]).ok().unwrap();
+        assert_eq!(config.experiment.regrowth, Regrowth::Logistic);
+        assert_eq!(config.experiment.allocation, Allocation::FirstCome);
+
+        let config = load_with(&[
+            "--set",
+            "experiment.regrowth=geometric",
+            "--set",
+            "experiment.allocation=proportional",
+        ])
+        .ok()
+        .unwrap();
+        assert_eq!(config.experiment.regrowth, Regrowth::Geometric);
+        assert_eq!(config.experiment.allocation, Allocation::Proportional);
+
+        assert!(load_with(&["--set", "experiment.allocation=2"]).is_err());
+        assert!(load_with(&["--set", "experiment.regrowth=linear"]).is_err());
+    }
+
+    #[test]
+    fn population_groups_come_first_and_the_rest_are_of_the_default_type() {
+        let mut config = Config::default();
+        config.experiment.n_agents = 5;
+        config.population = vec![
+            AgentGroup {
+                count: 1,
+                agent_type: AgentType::FIXED { amount: 2 },
+            },
+            AgentGroup {
+                count: 2,
+                agent_type: AgentType::SUSTAINABLE,
+            },
+        ];
+        assert_eq!(
+            config.agent_types(),
+            [
+                AgentType::FIXED { amount: 2 },
+                AgentType::SUSTAINABLE,
+                AgentType::SUSTAINABLE,
+                AgentType::QLEARNING,
+                AgentType::QLEARNING,
+            ]
+        );
+        assert!(config.validate().is_empty());
+
+        config.population[1].count = 5;
+        assert_eq!(config.agent_types().len(), 5);
+        assert_eq!(issue_keys(&config.validate()), ["population"]);
     }
 }
@@ -1,21 +1,31 @@
-use csv::Writer;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand_pcg::Pcg64;
 use std::collections::HashMap;
-use std::fs::File;
+use std::error::Error;
+use std::io;
+use std::path::Path;
 
 use indicatif::{ProgressBar, ProgressIterator};
 
 use crate::CONFIG;
-use crate::agent::structs::QTable;
+use crate::agent::models::save_models;
+use crate::agent::structs::{signal_distribution, HarvestObservation, QTable};
+use crate::calculator::sustainable_share;
+use crate::checkpoint::{Checkpoint, CheckpointRef, CHECKPOINT_VERSION};
+use crate::output::ExperimentWriters;
 use crate::statistics::RLStatistics;
+use crate::stopping::{ConvergenceMonitor, RunSummary, StopReason};
+use crate::table::Table;
 
 // Aliases
 use super::agent::structs::AgentState;
 use super::agent::Agent;
 use super::commons::Commons;
 use super::config::ExperimentConfig;
-use super::statistics::{EpochStatistics, GenerationStatistics};
+use super::statistics::{
+    AgeStatistics, AgentStatistics, EpochStatistics, EpochVoting, GenerationStatistics,
+    LearningDiagnostics, Life, LifeEnd,
+};
 
 pub struct Experiment {
     n_generations: i32,
@@ -23,6 +33,21 @@ pub struct Experiment {
     agents: Vec<Agent>,
     commons: Commons,
     config: ExperimentConfig,
+    rng: Pcg64,
+    max_sustainable_yield: i32,
+    /// The largest harvest that keeps an agent's reputation
+    reputation_norm: i32,
+    /// The index of the quota the agents voted for, when voting is enabled
+    quota: Option<usize>,
+    /// The lives that ended in the last generation, when ageing is enabled
+    lives: Vec<Life>,
+    /// Whether the agents are being evaluated, in which no agent is replaced
+    evaluating: bool,
+    /// Greedy policy of each agent at the end of the previous generation, by agent id
+    greedy_policies: HashMap<i32, Vec<usize>>,
+    generations_ran: i32,
+    stop_reason: Option<StopReason>,
+    monitor: ConvergenceMonitor,
 }
 
 impl Experiment {
@@ -32,29 +57,276 @@ impl Experiment {
         agents: Vec<Agent>,
         commons: Commons,
         config: ExperimentConfig,
+        rng: Pcg64,
     ) -> Experiment {
+        let max_sustainable_yield = commons.max_sustainable_yield();
+        let greedy_policies = Self::greedy_policies(&agents);
+        let reputation_norm = CONFIG
+            .reputation
+            .norm
+            .unwrap_or_else(|| sustainable_share(&config));
         Experiment {
             n_generations,
             epochs_per_gen,
             agents,
             commons,
             config,
+            rng,
+            max_sustainable_yield,
+            reputation_norm,
+            quota: None,
+            lives: Vec::new(),
+            evaluating: false,
+            greedy_policies,
+            generations_ran: 0,
+            stop_reason: None,
+            monitor: ConvergenceMonitor::new(CONFIG.stopping),
         }
     }
 
+    fn greedy_policies(agents: &[Agent]) -> HashMap<i32, Vec<usize>> {
+        agents
+            .iter()
+            .map(|agent| (agent.id, agent.greedy_policy()))
+            .collect()
+    }
+
+    /// Fraction of states, over all agents, in which the greedy action changed
+    /// since the last call
+    fn update_greedy_policies(&mut self) -> f32 {
+        let new_policies = Self::greedy_policies(&self.agents);
+        let mut n_states = 0;
+        let mut n_changed = 0;
+        for (agent_id, new_policy) in &new_policies {
+            if let Some(old_policy) = self.greedy_policies.get(agent_id) {
+                n_states += new_policy.len();
+                n_changed += new_policy
+                    .iter()
+                    .zip(old_policy)
+                    .filter(|(new_action, old_action)| new_action != old_action)
+                    .count();
+            }
+        }
+        self.greedy_policies = new_policies;
+        if n_states == 0 {
+            0.
+        } else {
+            n_changed as f32 / n_states as f32
+        }
+    }
+
+    /// Run the generations from `start_generation` onwards, writing a checkpoint
+    /// to `checkpoint_path`, if any, every `checkpoint_interval` generations and at the end.
+    /// Stops early when one of the configured stopping criteria is met.
     pub fn run_incremental_output(
         &mut self,
         pb: ProgressBar,
-        csv_writer: &mut Writer<File>,
-    ) -> RLStatistics {
-        for gen_idx in (0..self.n_generations).progress_with(pb) {
+        writers: &mut ExperimentWriters,
+        start_generation: i32,
+        checkpoint_path: Option<&Path>,
+    ) -> io::Result<RLStatistics> {
+        let checkpoint_interval = match checkpoint_path {
+            Some(_) => CONFIG.simulation.checkpoint_interval,
+            None => 0,
+        };
+        let eval_cfg = CONFIG.evaluation;
+        self.generations_ran = start_generation;
+        pb.set_position(start_generation as u64);
+        // An experiment that stopped early before its checkpoint has nothing left to train
+        let end_generation = match self.stop_reason {
+            Some(reason) => {
+                pb.abandon_with_message(format!("stopped early: {:?}", reason));
+                start_generation
+            }
+            None => self.n_generations,
+        };
+        for gen_idx in (start_generation..end_generation).progress_with(pb.clone()) {
             let gen_stats = self.single_generation(gen_idx);
             gen_stats
-                .append_to_csv(csv_writer)
-                .expect("Cannot write generation stats");
+                .append_to(&mut writers.gen_stats)
+                .map_err(write_error("generation stats"))?;
+            writers
+                .generations
+                .send_generation(gen_idx, gen_stats.aggregate_values());
+            if let Some(table) = &mut writers.agent_stats {
+                gen_stats
+                    .append_agents_to(table)
+                    .map_err(write_error("agent stats"))?;
+            }
+            if let Some(table) = &mut writers.age_stats {
+                table
+                    .serialize(AgeStatistics::new(gen_idx, &self.lives))
+                    .map_err(write_error("age stats"))?;
+            }
+            if let Some(table) = &mut writers.voting_stats {
+                gen_stats
+                    .append_voting_to(table)
+                    .map_err(write_error("voting stats"))?;
+            }
+            if let Some(table) = &mut writers.reputation_stats {
+                gen_stats
+                    .append_reputations_to(table)
+                    .map_err(write_error("reputation stats"))?;
+            }
+            if let Some(table) = &mut writers.signal_stats {
+                gen_stats
+                    .append_signals_to(table)
+                    .map_err(write_error("signal stats"))?;
+            }
+            if let Some(table) = &mut writers.epoch_trace {
+                if CONFIG.output.traces_generation(gen_idx, self.n_generations) {
+                    gen_stats
+                        .append_epochs_to(table)
+                        .map_err(write_error("epoch trace"))?;
+                }
+            }
+            let trained_generations = gen_idx + 1;
+            self.generations_ran = trained_generations;
+            self.stop_reason = self.monitor.update(&gen_stats);
+            let last_generation =
+                self.stop_reason.is_some() || trained_generations == self.n_generations;
+            if let Some(table) = &mut writers.qtable_history {
+                if trained_generations % CONFIG.output.qtable_snapshot_interval == 0 {
+                    self.snapshot_q_tables(gen_idx, table)
+                        .map_err(write_error("Q-table history"))?;
+                }
+            }
+            if (eval_cfg.interval > 0 && trained_generations % eval_cfg.interval == 0)
+                || (eval_cfg.at_end && last_generation)
+            {
+                self.evaluate(trained_generations, writers)?;
+            }
+            if checkpoint_interval > 0 && trained_generations % checkpoint_interval == 0 {
+                // Flush first, so the stats on disk always cover the checkpointed generations
+                writers.flush().map_err(write_error("generation stats"))?;
+                self.save_checkpoint(checkpoint_path.unwrap(), trained_generations)
+                    .map_err(write_error("checkpoint"))?;
+            }
+            if let Some(reason) = self.stop_reason {
+                pb.abandon_with_message(format!("stopped early: {:?}", reason));
+                break;
+            }
+        }
+        writers.finish().map_err(write_error("generation stats"))?;
+        if checkpoint_interval > 0 {
+            self.save_checkpoint(checkpoint_path.unwrap(), self.generations_ran)
+                .map_err(write_error("checkpoint"))?;
         }
 
-        RLStatistics::new(QTable::average_q_table(&self.agents))
+        Ok(RLStatistics::new(QTable::average_q_table(&self.agents)))
+    }
+
+    pub fn run_summary(&self) -> RunSummary {
+        RunSummary {
+            generations_ran: self.generations_ran,
+            stopped_early: self.stop_reason.is_some(),
+            stop_reason: self.stop_reason,
+        }
+    }
+
+    /// Append the average Q-table, and optionally those of the individual agents,
+    /// to the Q-table history
+    fn snapshot_q_tables(
+        &self,
+        generation_number: i32,
+        table: &mut Table,
+    ) -> Result<(), Box<dyn Error>> {
+        RLStatistics::new(QTable::average_q_table(&self.agents)).append_history_to(
+            generation_number,
+            -1,
+            table,
+        )?;
+        if CONFIG.output.qtable_snapshot_per_agent {
+            let mut agents: Vec<&Agent> = self.agents.iter().collect();
+            agents.sort_by_key(|agent| agent.id);
+            for agent in agents {
+                RLStatistics::new(agent.get_q_table().clone()).append_history_to(
+                    generation_number,
+                    agent.id,
+                    table,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Run the evaluation generations: agents follow their greedy policy (up to the
+    /// evaluation epsilon) and do not learn, so their Q-tables stay untouched
+    fn evaluate(
+        &mut self,
+        trained_generations: i32,
+        writers: &mut ExperimentWriters,
+    ) -> io::Result<()> {
+        let eval_cfg = CONFIG.evaluation;
+        let eval_table = writers
+            .eval_stats
+            .as_mut()
+            .expect("Evaluation stats output is not open");
+
+        self.agents
+            .iter_mut()
+            .for_each(|agent| agent.set_evaluation(Some(eval_cfg.epsilon)));
+        self.evaluating = true;
+        for eval_idx in 0..eval_cfg.n_generations {
+            self.single_generation(eval_idx)
+                .append_eval_to(trained_generations, eval_table)
+                .map_err(write_error("evaluation stats"))?;
+        }
+        self.evaluating = false;
+        self.agents
+            .iter_mut()
+            .for_each(|agent| agent.set_evaluation(None));
+        Ok(())
+    }
+
+    /// Write the learned Q-tables and actions of all agents, to warm-start later experiments
+    pub fn save_agent_models(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        save_models(&self.agents, path)
+    }
+
+    /// Store the agents, commons and random number generator, to continue
+    /// from `next_generation` later on
+    pub fn save_checkpoint(&self, path: &Path, next_generation: i32) -> Result<(), Box<dyn Error>> {
+        CheckpointRef {
+            version: CHECKPOINT_VERSION,
+            next_generation,
+            agents: &self.agents,
+            resource_pool: self.commons.resource_pool,
+            depleted: self.commons.depleted,
+            rng: &self.rng,
+            monitor: self.monitor.for_checkpoint(),
+            stop_reason: self.stop_reason,
+        }
+        .save(path)
+    }
+
+    /// Restore the experiment from a checkpoint, returning the generation to continue from
+    pub fn restore_checkpoint(&mut self, path: &Path) -> io::Result<i32> {
+        let checkpoint = Checkpoint::load(path).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Could not restore checkpoint {}: {}", path.display(), e),
+            )
+        })?;
+        if checkpoint.agents.len() != self.agents.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "Checkpoint {} holds {} agents, the configuration asks for {}",
+                    path.display(),
+                    checkpoint.agents.len(),
+                    self.agents.len()
+                ),
+            ));
+        }
+        self.agents = checkpoint.agents;
+        self.greedy_policies = Self::greedy_policies(&self.agents);
+        self.commons.resource_pool = checkpoint.resource_pool;
+        self.commons.depleted = checkpoint.depleted;
+        self.rng = checkpoint.rng;
+        self.monitor = checkpoint.monitor;
+        self.stop_reason = checkpoint.stop_reason;
+        Ok(checkpoint.next_generation)
     }
 
     /// Run one generation, executing epochs until the commons
@@ -68,6 +340,7 @@ impl Experiment {
         self.agents
             .iter_mut()
             .for_each(|agent| agent.update_state(pool));
+        self.lives.clear();
 
         let mut epochs_stats: Vec<EpochStatistics> =
             Vec::with_capacity(self.epochs_per_gen as usize);
@@ -90,6 +363,45 @@ impl Experiment {
             current_epoch += 1;
         }
 
+        let mut agents_stats: Vec<AgentStatistics> = self
+            .agents
+            .iter()
+            .map(|agent| AgentStatistics::new(generation_number, agent))
+            .collect();
+        agents_stats.sort_by_key(|agent_stats| agent_stats.agent_id);
+
+        let (td_error_sum, n_updates) = self
+            .agents
+            .iter_mut()
+            .map(|agent| agent.take_td_errors())
+            .fold((0., 0), |(sum, n), (agent_sum, agent_n)| {
+                (sum + agent_sum, n + agent_n)
+            });
+        let diagnostics = LearningDiagnostics {
+            mean_abs_td_error: if n_updates > 0 {
+                td_error_sum / n_updates as f32
+            } else {
+                0.
+            },
+            states_visited: GenerationStatistics::count_states_visited(&epochs_stats),
+            policy_change_rate: self.update_greedy_policies(),
+        };
+
+        if CONFIG.ageing.enabled() {
+            for agent in self.agents.iter().filter(|agent| agent.is_alive()) {
+                self.lives.push(Life {
+                    age: agent.days_lived,
+                    end: LifeEnd::Survived,
+                });
+            }
+        }
+
+        // The quota lapses with the generation
+        self.agents
+            .iter_mut()
+            .for_each(|agent| agent.close_ballot());
+        self.quota = None;
+
         // Revive all agents and reset commons
         self.agents.iter_mut().for_each(|agent| agent.revive());
         self.commons.reset();
@@ -97,16 +409,61 @@ impl Experiment {
         GenerationStatistics::new(
             generation_number,
             epochs_stats,
+            agents_stats,
             reached_equilibrium,
             final_agents_alive,
+            self.max_sustainable_yield,
+            diagnostics,
         )
     }
 
+    /// Let the living agents vote on the quota and enforce the winner, returning the ballots
+    fn hold_election(&mut self) -> Vec<usize> {
+        let rng = &mut self.rng;
+        let ballots: Vec<usize> = self
+            .agents
+            .iter_mut()
+            .filter(|agent| agent.is_alive())
+            .map(|agent| agent.decide_vote(rng))
+            .collect();
+        self.quota = CONFIG
+            .voting
+            .rule
+            .winner(&ballots, &CONFIG.voting.quotas)
+            .or(self.quota);
+        ballots
+    }
+
+    /// Update the reputations of the agents that harvested in this epoch, and show each
+    /// of them the mean reputation of the others
+    fn judge_harvests(&mut self) {
+        let (norm, rate) = (self.reputation_norm, CONFIG.reputation.rate);
+        let mut reputation_sum = 0.;
+        let mut n_judged = 0;
+        for agent in self.agents.iter_mut().filter(|agent| agent.is_alive()) {
+            agent.update_reputation(norm, rate);
+            reputation_sum += agent.reputation;
+            n_judged += 1;
+        }
+        for agent in self.agents.iter_mut().filter(|agent| agent.is_alive()) {
+            agent.observe_reputation(if n_judged > 1 {
+                Some((reputation_sum - agent.reputation) / (n_judged - 1) as f32)
+            } else {
+                None
+            });
+        }
+    }
+
     /// Execute a single epoch in the generation: each agent
     /// executes one action, and the commons regrows.
     fn single_epoch(&mut self, epoch_number: i32) -> EpochStatistics {
         // Shuffle the agents vector before taking actions to avoid order-based behavior
-        self.agents.shuffle(&mut thread_rng());
+        self.agents.shuffle(&mut self.rng);
+        if CONFIG.reputation.priority {
+            // Agents with better reputations take first, the stable sort keeps ties shuffled
+            self.agents
+                .sort_by(|a, b| b.reputation.total_cmp(&a.reputation));
+        }
 
         let mut chosen_actions: HashMap<String, Vec<i32>> = AgentState::state_keys()
             .iter()
@@ -117,36 +474,122 @@ impl Experiment {
                 )
             })
             .collect();
+        let mut harvested = 0;
+        let mut requests: Vec<i32> = Vec::with_capacity(self.agents.len());
 
-        for agent in &mut self.agents {
+        let ballots = if CONFIG.voting.enabled() && CONFIG.voting.votes_in(epoch_number) {
+            self.hold_election()
+        } else {
+            Vec::new()
+        };
+        let quota = self.quota.map(|option| CONFIG.voting.quotas[option]);
+        let ageing = CONFIG.ageing;
+
+        // Cheap talk: every living agent signals before anyone harvests, and all of them
+        // hear how the signals were distributed
+        let signals: Vec<Option<i32>> = if CONFIG.communication.enabled() {
+            let rng = &mut self.rng;
+            self.agents
+                .iter_mut()
+                .map(|agent| {
+                    if agent.is_alive() {
+                        Some(agent.decide_signal(rng))
+                    } else {
+                        None
+                    }
+                })
+                .collect()
+        } else {
+            vec![None; self.agents.len()]
+        };
+        let emitted: Vec<i32> = signals.iter().flatten().copied().collect();
+        if CONFIG.communication.enabled() {
+            let distribution = signal_distribution(&emitted, CONFIG.communication.vocabulary);
+            self.agents
+                .iter_mut()
+                .filter(|agent| agent.is_alive())
+                .for_each(|agent| agent.hear(&distribution));
+        }
+        let mut signalled_actions: Vec<(i32, i32)> = Vec::with_capacity(emitted.len());
+        let mut allowed: Vec<i32> = Vec::with_capacity(self.agents.len());
+
+        for (agent, signal) in self.agents.iter_mut().zip(&signals) {
             if agent.is_alive() {
-                agent.decide_action();
+                agent.decide_action(&mut self.rng);
                 let desired_resources = agent.desired_resources();
+                requests.push(desired_resources);
+                if let Some(signal) = *signal {
+                    signalled_actions.push((signal, desired_resources));
+                }
                 if let Some(state) = agent.get_current_state() {
                     chosen_actions.get_mut(&state.to_string()).unwrap()
                         [desired_resources as usize] += 1;
                 }
-                let taken_resources = self.commons.take_resources(desired_resources);
-                agent.get_resources(taken_resources);
+                // The quota caps what an agent gets, not what it asked for
+                let mut allowed_resources =
+                    quota.map_or(desired_resources, |quota| desired_resources.min(quota));
+                if ageing.enabled() {
+                    allowed_resources = allowed_resources
+                        .min(ageing.capacity(self.config.n_actions - 1, agent.days_lived));
+                }
+                allowed.push(allowed_resources);
             }
         }
+        // The agents ask in turn, and the allocation rule decides what each of them gets
+        let taken = self.commons.allocate(&allowed, self.config.allocation);
+        for (agent, taken_resources) in self
+            .agents
+            .iter_mut()
+            .filter(|agent| agent.is_alive())
+            .zip(taken)
+        {
+            harvested += taken_resources;
+            agent.get_resources(taken_resources);
+        }
+
+        if CONFIG.reputation.enabled {
+            self.judge_harvests();
+        }
 
         // Commons grow after agents take, and before they asses the new state.
         // In this way, they see the indirect effect of their behaviour -- they learn what
         // the new state will be, not what it is directly after.
         self.commons.grow();
+        let observation = HarvestObservation::new(&requests, self.config.n_actions);
 
         // At days end, agents consume their food, go to sleep and see what
         // their cumulative actions have done to the commons, and their own food supply
         for agent in &mut self.agents {
             if agent.is_alive() {
                 // let debug_print = true if agent.get_current_state().unwrap()
-                agent.consume(self.config.consumption);
+                agent.consume(ageing.consumption(self.config.consumption, agent.days_lived));
                 if CONFIG.experiment.empty_commons_purge && self.commons.depleted {
                     agent.kill();
                 }
                 agent.update_state( self.commons.resource_pool);
+                agent.observe(&observation);
                 agent.learn();
+                // Evaluated agents grow old without dying of it, so no newcomer takes the
+                // place of a trained agent
+                if !ageing.enabled() || self.evaluating {
+                    continue;
+                }
+                if !agent.is_alive() {
+                    self.lives.push(Life {
+                        age: agent.days_lived,
+                        end: LifeEnd::Died,
+                    });
+                } else if agent.days_lived >= ageing.max_age {
+                    agent.die_of_age();
+                    self.lives.push(Life {
+                        age: agent.days_lived,
+                        end: LifeEnd::OldAge,
+                    });
+                    if ageing.replace {
+                        agent.replace_by_newcomer(self.config.n_actions, &mut self.rng);
+                        agent.update_state(self.commons.resource_pool);
+                    }
+                }
             }
         }
 
@@ -161,7 +604,120 @@ impl Experiment {
             epoch_number,
             self.agents.iter().filter(|agent| agent.is_alive()).count() as i32,
             self.commons.resource_pool,
+            harvested,
+            self.commons.depleted,
             chosen_actions,
+            signalled_actions,
         )
+        .with_voting(self.quota.map(|quota| EpochVoting { ballots, quota }))
+    }
+}
+
+/// Turn a failed write of the named output into an IO error
+fn write_error(output: &'static str) -> impl Fn(Box<dyn Error>) -> io::Error {
+    move |e| io::Error::other(format!("Cannot write {}: {}", output, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aggregate::{ExperimentMessage, StreamingAggregator};
+    use crate::statistics::AggregateRow;
+    use crate::table::OutputFormat;
+    use rand::SeedableRng;
+    use std::fs;
+
+    fn experiment(seed: u64) -> Experiment {
+        let cfg = CONFIG.experiment;
+        let mut rng = Pcg64::seed_from_u64(seed);
+        let agents = crate::make_agents(&CONFIG.agent_types(), cfg.n_actions, None, &mut rng);
+        let commons = Commons::new(
+            cfg.init_pool_size,
+            cfg.max_pool_size,
+            cfg.regrowth,
+            cfg.regrowth_rate,
+        );
+        Experiment::new(
+            cfg.n_generations,
+            cfg.epochs_per_gen,
+            agents,
+            commons,
+            cfg,
+            rng,
+        )
+    }
+
+    #[test]
+    fn resumed_experiment_continues_like_the_original() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint_path = dir.path().join("checkpoint.json");
+        let mut original = experiment(7);
+        for gen_num in 0..3 {
+            original.single_generation(gen_num);
+        }
+        original.save_checkpoint(&checkpoint_path, 3).unwrap();
+
+        // Seeded differently, so everything that matches comes from the checkpoint
+        let mut resumed = experiment(8);
+        assert_eq!(resumed.restore_checkpoint(&checkpoint_path).unwrap(), 3);
+        for gen_num in 3..6 {
+            let original_stats = original.single_generation(gen_num);
+            let resumed_stats = resumed.single_generation(gen_num);
+            assert_eq!(original_stats.epochs_ran(), resumed_stats.epochs_ran());
+        }
+        // As values, since the Q-tables are hash maps without a fixed order
+        assert_eq!(
+            serde_json::to_value(&original.agents).unwrap(),
+            serde_json::to_value(&resumed.agents).unwrap()
+        );
+        assert_eq!(original.rng, resumed.rng);
+    }
+
+    /// Average a single experiment's generations into `avg_gen_stats.csv` in `dir`
+    fn average(dir: &Path, rows: Vec<AggregateRow>) -> String {
+        let aggregator = StreamingAggregator::new(dir, 1, &OutputFormat::Csv).unwrap();
+        aggregator
+            .run(
+                rows.into_iter()
+                    .map(|(gen_num, values)| ExperimentMessage::Generation {
+                        exp_idx: 0,
+                        gen_num,
+                        values,
+                    }),
+            )
+            .unwrap();
+        fs::read_to_string(dir.join("avg_gen_stats.csv")).unwrap()
+    }
+
+    #[test]
+    fn resumed_averages_match_those_of_an_uninterrupted_run() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut experiment = experiment(3);
+        let mut gen_stats = Table::create(
+            dir.path(),
+            "gen_stats",
+            GenerationStatistics::csv_header(),
+            &OutputFormat::Csv,
+        )
+        .unwrap();
+        let live: Vec<AggregateRow> = (0..5)
+            .map(|gen_num| {
+                let stats = experiment.single_generation(gen_num);
+                stats.append_to(&mut gen_stats).unwrap();
+                (gen_num, stats.aggregate_values())
+            })
+            .collect();
+        gen_stats.finish().unwrap();
+
+        // A resumed run averages the generations before its checkpoint from gen_stats.csv
+        let read_back =
+            GenerationStatistics::read_aggregate_values(&dir.path().join("gen_stats.csv")).unwrap();
+        assert_eq!(read_back, live);
+        let live_dir = tempfile::tempdir().unwrap();
+        let resumed_dir = tempfile::tempdir().unwrap();
+        assert_eq!(
+            average(live_dir.path(), live),
+            average(resumed_dir.path(), read_back)
+        );
     }
 }
@@ -1,199 +1,545 @@
 // Modules
 mod agent;
+mod aggregate;
+mod calculator;
+mod checkpoint;
 mod commons;
 mod config;
 mod experiment;
+mod manifest;
+mod output;
+mod results_db;
 mod statistics;
+mod stopping;
+mod table;
 
 // Aliases
 use std::error::Error;
+use std::fmt;
 use std::fs;
-use std::fs::OpenOptions;
-use std::path::PathBuf;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::channel;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
 
-use csv::WriterBuilder;
 use dialoguer::Confirm;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use once_cell::sync::Lazy;
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64;
 use structopt::StructOpt;
 
-use agent::structs::AgentState;
+use agent::models::{load_models, AgentModel};
+use agent::structs::AgentType;
 use agent::Agent;
+use aggregate::{GenerationSender, StreamingAggregator};
+use calculator::Equilibrium;
 use commons::Commons;
-use config::{CommandLineArgs, Config};
+use config::{Command, CommandLineArgs, Config, ConfigIssue, OnExisting};
 use experiment::Experiment;
-use statistics::{
-    AverageExperimentStatistics, ExperimentOutput, ExperimentStatistics, GenerationStatistics,
-    RLStatistics,
-};
-
-static CONFIG: Lazy<Config> = Lazy::new(|| match CommandLineArgs::from_args().config_path {
-    Some(path) => confy::load_path(path).unwrap(),
-    _ => Default::default(),
+use manifest::{panic_message, Manifest, ManifestWriter, RunStatus};
+use output::ExperimentWriters;
+use results_db::ResultsDb;
+use statistics::{GenerationStatistics, RLStatistics};
+use table::OutputFormat;
+
+#[cfg(not(test))]
+static CONFIG: Lazy<Config> = Lazy::new(|| {
+    let args = CommandLineArgs::from_args();
+    let (path, overrides) = match args.resume_dir() {
+        // A resumed run continues with the configuration it was started with
+        Some(resume_dir) => (Some(resume_dir.join("experiment.toml")), Vec::new()),
+        None => (args.config_path.clone(), args.config_overrides()),
+    };
+    Config::load(path.as_deref(), &overrides).unwrap_or_else(|issues| {
+        let error = config_error(path.as_deref(), issues);
+        eprintln!("{}", error);
+        std::process::exit(error.exit_code());
+    })
 });
 
-fn make_agents(n_agents: i32, n_actions: i32) -> Vec<Agent> {
-    let mut agents: Vec<Agent> = Vec::with_capacity(n_agents as usize);
+// The test harness has command line arguments of its own
+#[cfg(test)]
+static CONFIG: Lazy<Config> = Lazy::new(Config::default);
+
+/// Why a run failed, which decides the exit code so batch jobs can tell failures apart
+#[derive(Debug)]
+enum RunError {
+    Config(String),
+    Io(String),
+    Simulation(String),
+}
+
+impl RunError {
+    fn exit_code(&self) -> i32 {
+        match self {
+            RunError::Config(_) => 2,
+            RunError::Io(_) => 3,
+            RunError::Simulation(_) => 4,
+        }
+    }
+}
+
+fn config_error(path: Option<&Path>, issues: Vec<ConfigIssue>) -> RunError {
+    let issues: Vec<String> = issues
+        .iter()
+        .map(|issue| format!("    {}", issue))
+        .collect();
+    RunError::Config(format!(
+        "Invalid configuration{}:\n{}",
+        path.map_or(String::new(), |path| format!(" {}", path.display())),
+        issues.join("\n")
+    ))
+}
 
-    for id in 0..n_agents {
-        agents.push(Agent::new(id, None, n_actions));
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunError::Config(message) | RunError::Io(message) | RunError::Simulation(message) => {
+                write!(f, "{}", message)
+            }
+        }
     }
-    return agents;
 }
 
-fn regrow(current_amount: i32, regrowth_rate: f32) -> i32 {
-    (current_amount as f32 * regrowth_rate) as i32
+/// Create an agent of each of the given types, optionally starting from saved models. When
+/// there are fewer models than agents, the models are reused in order.
+fn make_agents(
+    agent_types: &[AgentType],
+    n_actions: i32,
+    models: Option<&[AgentModel]>,
+    rng: &mut impl Rng,
+) -> Vec<Agent> {
+    let mut agents: Vec<Agent> = Vec::with_capacity(agent_types.len());
+
+    for (id, &agent_type) in agent_types.iter().enumerate() {
+        let id = id as i32;
+        let mut agent = Agent::new(id, None, n_actions, agent_type, rng);
+        if let Some(models) = models {
+            agent.load_model(&models[id as usize % models.len()]);
+        }
+        agents.push(agent);
+    }
+    agents
 }
 
 fn run_experiments_incremental_output(
     n_experiments: i32,
-    output_dir: PathBuf,
+    output_dir: Option<PathBuf>,
+    resume: bool,
+    format: OutputFormat,
+    quiet: bool,
+) -> Result<RunStatus, Box<dyn Error>> {
+    let seeds: Vec<u64> = (0..n_experiments)
+        .map(|exp_idx| CONFIG.simulation.experiment_seed(exp_idx))
+        .collect();
+    let manifest = match &output_dir {
+        Some(output_dir) => {
+            fs::create_dir_all(output_dir)?;
+
+            if !resume {
+                let mut exp_config_path = output_dir.clone();
+                exp_config_path.push("experiment.toml");
+                confy::store_path(exp_config_path, CONFIG.clone())?;
+            }
+
+            let manifest_path = output_dir.join("manifest.json");
+            if resume && manifest_path.exists() {
+                ManifestWriter::resume(manifest_path)?
+            } else {
+                ManifestWriter::create(Some(manifest_path), Manifest::new(&seeds))?
+            }
+        }
+        // The results database records the configuration and the seeds instead
+        None => ManifestWriter::create(None, Manifest::new(&seeds))?,
+    };
+    let manifest = Arc::new(manifest);
+
+    let result = run_experiments(
+        n_experiments,
+        output_dir.as_deref(),
+        resume,
+        &format,
+        &manifest,
+        quiet,
+    );
+    let status = manifest.finish(result.is_err())?;
+    result.map(|_| status)
+}
+
+/// Run the experiments in parallel, averaging their statistics as they come in. Without an
+/// output directory only the results database is written to.
+fn run_experiments(
+    n_experiments: i32,
+    output_dir: Option<&Path>,
+    resume: bool,
+    format: &OutputFormat,
+    manifest: &Arc<ManifestWriter>,
+    quiet: bool,
 ) -> Result<(), Box<dyn Error>> {
     let cfg = CONFIG.experiment;
 
-    fs::create_dir_all(&output_dir)?;
-
-    let mut exp_config_path = output_dir.clone();
-    exp_config_path.push("experiment.toml");
-    confy::store_path(exp_config_path, *CONFIG)?;
+    let models: Option<Arc<Vec<AgentModel>>> = match CONFIG.rl_params.model_path() {
+        Some(path) => Some(Arc::new(load_models(Path::new(path), cfg.n_actions)?)),
+        None => None,
+    };
 
+    let seeds = manifest.seeds();
     let multi_progress = MultiProgress::new();
+    if quiet {
+        multi_progress.set_draw_target(ProgressDrawTarget::hidden());
+    }
     let (sender, receiver) = channel();
+    // Every experiment holds a clone until it is recorded in the manifest
+    let (recorded_sender, recorded_receiver) = channel::<()>();
+    // Experiments that could not read or write their files
+    let io_errors: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+    // SQLite tables do not use the directory, and are the only tables without one
+    let tables_dir = output_dir.unwrap_or_else(|| Path::new(""));
+    let aggregator = StreamingAggregator::new(tables_dir, n_experiments as usize, format)?;
+    // Only runs with csv output can be resumed, so only they are checkpointed
+    let checkpointed = output_dir.is_some() && matches!(format, OutputFormat::Csv);
     for exp_idx in 0..n_experiments {
         let pb = multi_progress.add(ProgressBar::new(cfg.n_generations as u64));
         pb.set_style(
             ProgressStyle::default_bar()
                 .template("[{elapsed_precise}] {bar:40.green} {pos:>7}/{len:7} {msg}"),
         );
-        let new_sender = sender.clone();
-        let mut exp_output_dir = output_dir.clone();
-        exp_output_dir.push(exp_idx.to_string());
-        fs::create_dir_all(&exp_output_dir)?;
+        let generation_sender = GenerationSender::new(exp_idx as usize, sender.clone());
+        let seed = seeds[exp_idx as usize];
+        let exp_format = format.for_experiment(exp_idx, seed)?;
+        let models = models.clone();
+        let manifest = manifest.clone();
+        let recorded = recorded_sender.clone();
+        let io_errors = io_errors.clone();
+        let exp_output_dir = output_dir.map(|output_dir| output_dir.join(exp_idx.to_string()));
+        if let Some(exp_output_dir) = &exp_output_dir {
+            fs::create_dir_all(exp_output_dir)?;
+        }
+        let exp_tables_dir = exp_output_dir
+            .clone()
+            .unwrap_or_else(|| tables_dir.to_path_buf());
         rayon::spawn(move || {
-            let mut experiment = Experiment::new(
-                cfg.n_generations,
-                cfg.epochs_per_gen,
-                make_agents(cfg.n_agents, cfg.n_actions),
-                Commons::new(
-                    cfg.init_pool_size as i32,
-                    cfg.max_pool_size as i32,
-                    regrow,
-                    cfg.regrowth_rate,
-                ),
-                cfg,
-            );
-            let mut gen_stats_path = exp_output_dir.clone();
-            gen_stats_path.push("gen_stats.csv");
-            let mut gen_stats_csv_writer = WriterBuilder::new().has_headers(false).from_writer(
-                OpenOptions::new()
-                    .write(true)
-                    .create(true)
-                    .append(true)
-                    .open(gen_stats_path)
-                    .unwrap(),
-            );
-            gen_stats_csv_writer
-                .write_record(GenerationStatistics::csv_header())
-                .expect("Could not write gen stats header");
-            let rl_stats = experiment.run_incremental_output(pb, &mut gen_stats_csv_writer);
-            let mut rl_stats_path = exp_output_dir.clone();
-            rl_stats_path.push("rl_stats.csv");
-            match rl_stats.to_csv(&rl_stats_path) {
-                Ok(_) => (),
-                Err(e) => println!("Failed to write average RL Statistics: \n {}", e),
+            let start_time = Instant::now();
+            // A panicking experiment is recorded as crashed, instead of aborting the others
+            let outcome = panic::catch_unwind(AssertUnwindSafe(move || -> io::Result<_> {
+                let mut rng = Pcg64::seed_from_u64(seed);
+                let agents = make_agents(
+                    &CONFIG.agent_types(),
+                    cfg.n_actions,
+                    models.as_deref().map(|m| m.as_slice()),
+                    &mut rng,
+                );
+                let mut experiment = Experiment::new(
+                    cfg.n_generations,
+                    cfg.epochs_per_gen,
+                    agents,
+                    Commons::new(
+                        cfg.init_pool_size,
+                        cfg.max_pool_size,
+                        cfg.regrowth,
+                        cfg.regrowth_rate,
+                    ),
+                    cfg,
+                    rng,
+                );
+                let checkpoint_path = exp_output_dir
+                    .as_ref()
+                    .filter(|_| checkpointed)
+                    .map(|exp_output_dir| exp_output_dir.join("checkpoint.json"));
+                let start_generation = match &checkpoint_path {
+                    Some(checkpoint_path) if resume && checkpoint_path.exists() => {
+                        experiment.restore_checkpoint(checkpoint_path)?
+                    }
+                    _ => 0,
+                };
+                let mut writers = ExperimentWriters::open(
+                    &exp_tables_dir,
+                    start_generation,
+                    &exp_format,
+                    generation_sender,
+                )
+                .map_err(|e| {
+                    io::Error::other(format!("Could not open experiment output files: {}", e))
+                })?;
+                if start_generation > 0 {
+                    // Include the generations before the checkpoint in the averages again
+                    let gen_stats_path = exp_tables_dir.join("gen_stats.csv");
+                    let rows = GenerationStatistics::read_aggregate_values(&gen_stats_path)
+                        .map_err(|e| {
+                            io::Error::other(format!(
+                                "Could not read generation stats to resume from: {}",
+                                e
+                            ))
+                        })?;
+                    for (gen_num, values) in rows {
+                        writers.generations.send_generation(gen_num, values);
+                    }
+                }
+                let rl_stats = experiment.run_incremental_output(
+                    pb,
+                    &mut writers,
+                    start_generation,
+                    checkpoint_path.as_deref(),
+                )?;
+                let run_summary = experiment.run_summary();
+                if let Some(exp_output_dir) = &exp_output_dir {
+                    if let Err(e) = run_summary.save(&exp_output_dir.join("run_summary.json")) {
+                        println!("Failed to write run summary: \n {}", e);
+                    }
+                    if let Err(e) =
+                        experiment.save_agent_models(&exp_output_dir.join("q_tables.json"))
+                    {
+                        println!("Failed to write agent Q-tables: \n {}", e);
+                    }
+                }
+                if let OutputFormat::Sqlite(db) = &exp_format {
+                    if let Err(e) = db.finish_experiment(&run_summary) {
+                        println!("Failed to write run summary to the database: \n {}", e);
+                    }
+                }
+                match rl_stats.write_tables(&exp_tables_dir, "rl_stats", &exp_format) {
+                    Ok(_) => (),
+                    Err(e) => println!("Failed to write average RL Statistics: \n {}", e),
+                };
+                writers.generations.send_finished(rl_stats);
+                Ok(run_summary)
+            }));
+            // Failing to read or write files is an IO error of the run, not a crash
+            let outcome = match outcome {
+                Ok(Ok(run_summary)) => Ok(run_summary),
+                Ok(Err(e)) => {
+                    let message = e.to_string();
+                    io_errors
+                        .lock()
+                        .unwrap()
+                        .push(format!("experiment {}: {}", exp_idx, message));
+                    Err(message)
+                }
+                Err(payload) => Err(panic_message(&*payload)),
             };
-            new_sender.send(rl_stats).unwrap();
+            if let Err(e) = manifest.record_experiment(exp_idx, start_time.elapsed(), outcome) {
+                println!("Failed to update the run manifest: \n {}", e);
+            }
+            drop(recorded);
         });
     }
 
     drop(sender);
+    // Aggregate on a separate thread, the main thread draws the progress bars
+    let aggregator_handle =
+        thread::spawn(move || aggregator.run(receiver).map_err(|e| e.to_string()));
     multi_progress.join()?;
-    let mut avg_rl_stats_path = output_dir.clone();
-    avg_rl_stats_path.push("avg_rl_stats.csv");
-    match RLStatistics::average_from_vector(receiver.iter().collect()).to_csv(&avg_rl_stats_path) {
-        Ok(_) => println!("Succesfully wrote average RL Statistics"),
-        Err(e) => println!("Failed to write average RL Statistics: \n {}", e),
+    let rl_stats = match aggregator_handle.join() {
+        Ok(result) => {
+            result.map_err(|e| format!("Failed to write average generation stats: {}", e))?
+        }
+        Err(_) => return Err("Generation stats aggregator panicked".into()),
+    };
+    // The experiments report to the aggregator before the manifest, wait for both
+    drop(recorded_sender);
+    let _ = recorded_receiver.recv();
+    // When every experiment crashed there is nothing to average, the manifest tells why
+    if !rl_stats.is_empty() {
+        match RLStatistics::average_from_vector(rl_stats).write_tables(
+            tables_dir,
+            "avg_rl_stats",
+            format,
+        ) {
+            Ok(_) if !quiet => println!("Succesfully wrote average RL Statistics"),
+            Ok(_) => (),
+            Err(e) => println!("Failed to write average RL Statistics: \n {}", e),
+        }
     }
 
+    let io_errors = io_errors.lock().unwrap();
+    if !io_errors.is_empty() {
+        return Err(io_errors.join("\n").into());
+    }
     Ok(())
 }
 
-/// Write stats from a vector of experiment statistics
-/// Currently not in use because of memory limitations
-fn _write_stats(stats: Vec<ExperimentStatistics>, output_dir: PathBuf) {
-    // If a csv output path is given, attempt to write the experiment results to it
-    // TODO: validate this path is usable before running the whole experiment
-    let mut single_output_dir = output_dir.clone();
-    single_output_dir.push("experiments");
-    for (exp_idx, exp_stats) in stats.iter().enumerate() {
-        let mut exp_output_dir = single_output_dir.clone();
-        exp_output_dir.push(exp_idx.to_string());
-        fs::create_dir_all(&exp_output_dir).expect("Could not create experiment output dir");
-        match exp_stats.to_csvs(&exp_output_dir) {
-            Ok(_) => println!(
-                "Experiment #{} statistics succesfully written to {}",
-                exp_idx,
-                exp_output_dir.display()
-            ),
-            Err(e) => println!(
-                "Failed to write experiment #{} statistics: \n {}",
-                exp_idx, e
-            ),
-        };
+/// Decide where a new run writes to when its output directory already exists. Returns the
+/// directory and whether the run there is resumed.
+fn prepare_output_dir(
+    output_dir: PathBuf,
+    on_existing: OnExisting,
+    quiet: bool,
+) -> Result<(PathBuf, bool), RunError> {
+    if !output_dir.exists() {
+        return Ok((output_dir, false));
     }
-
-    let mut avg_output_dir = output_dir.clone();
-    avg_output_dir.push("avg_stats");
-    fs::create_dir_all(&avg_output_dir).expect("Could not create output dir");
-    match AverageExperimentStatistics::from_vector(&stats).to_csvs(&avg_output_dir) {
-        Ok(_) => println!(
-            "Average statistics succesfully written to {}",
-            avg_output_dir.display()
-        ),
-        Err(e) => println!("Failed to write average statistics: \n {}", e),
+    let remove = match on_existing {
+        OnExisting::Ask => Confirm::new()
+            .with_prompt("Output directory already exists. Remove old contents?")
+            .interact()
+            .map_err(|e| {
+                RunError::Io(format!("Could not ask about the output directory: {}", e))
+            })?,
+        OnExisting::Overwrite => true,
+        OnExisting::Fail => false,
+        OnExisting::Suffix => {
+            let name = output_dir.file_name().unwrap_or_default().to_string_lossy();
+            let free_dir = (1..)
+                .map(|n| output_dir.with_file_name(format!("{}_{}", name, n)))
+                .find(|dir| !dir.exists())
+                .unwrap();
+            if !quiet {
+                println!(
+                    "{} already exists, writing to {} instead",
+                    output_dir.display(),
+                    free_dir.display()
+                );
+            }
+            return Ok((free_dir, false));
+        }
+        OnExisting::Resume => {
+            return Err(RunError::Io(format!(
+                "Cannot resume in {}, it holds no experiment.toml of an earlier run",
+                output_dir.display()
+            )))
+        }
     };
+    if !remove {
+        return Err(RunError::Io(format!(
+            "Output directory {} already exists. Remove it, or choose what to do with --on-existing.",
+            output_dir.display()
+        )));
+    }
+    fs::remove_dir_all(&output_dir).map_err(|e| {
+        RunError::Io(format!(
+            "Failed to remove output directory and / or its contents: \n {}",
+            e
+        ))
+    })?;
+    if !quiet {
+        println!("Removed {} and its contents", output_dir.display());
+    }
+    Ok((output_dir, false))
 }
 
-fn main() {
-    let args = CommandLineArgs::from_args();
-
-    // TODO: Optionally, allow config file for sim
-    // config, instead of command line argument
-
-    println!(
-        "Running {} experiment{} with {} generations",
-        CONFIG.simulation.n_experiments,
-        if CONFIG.simulation.n_experiments > 1 {
-            "s"
-        } else {
-            ""
+fn run(args: CommandLineArgs) -> Result<(), RunError> {
+    let quiet = args.quiet;
+    let (output_dir, resume) = match args.resume_dir() {
+        Some(resume_dir) => {
+            if !args.config_overrides().is_empty() {
+                return Err(RunError::Config(
+                    "A resumed run continues with its stored configuration, it cannot be overridden"
+                        .to_string(),
+                ));
+            }
+            if args.config_path.is_some() {
+                eprintln!(
+                    "Ignoring the configuration file, a resumed run continues with {}",
+                    resume_dir.join("experiment.toml").display()
+                );
+            }
+            if !quiet {
+                println!("Resuming from the checkpoints in {}", resume_dir.display());
+            }
+            (Some(resume_dir), true)
+        }
+        None => match args.output_dir.clone() {
+            Some(output_dir) => {
+                let (output_dir, resume) =
+                    prepare_output_dir(output_dir, args.on_existing(), quiet)?;
+                (Some(output_dir), resume)
+            }
+            // A run into a results database alone writes no files
+            None => (None, false),
         },
-        CONFIG.experiment.n_generations
-    );
+    };
 
-    if args.output_dir.as_path().exists() {
-        if Confirm::new()
-            .with_prompt("Output directory already exists. Remove old contents?")
-            .interact()
-            .unwrap()
-        {
-            match fs::remove_dir_all(&args.output_dir) {
-                Ok(_) => println!("Removed {} and its contents", args.output_dir.display()),
-                Err(e) => println!(
-                    "Failed to remove output directory and / or its contents: \n {}",
+    let format = match &args.sqlite {
+        Some(path) => match ResultsDb::create_run(path, &CONFIG) {
+            Ok(db) => OutputFormat::Sqlite(db),
+            Err(e) => {
+                return Err(RunError::Io(format!(
+                    "Could not open results database {}: {}",
+                    path.display(),
                     e
-                ),
+                )))
             }
-        } else {
-            println!("Not removing existing output directory. Please run the simulation with a different output directory.");
-            return;
-        }
+        },
+        None => args.output_format(),
+    };
+    if resume && !matches!(format, OutputFormat::Csv) {
+        return Err(RunError::Config(
+            "Only runs with csv output can be resumed".to_string(),
+        ));
     }
 
-    if let Err(e) =
-        run_experiments_incremental_output(CONFIG.simulation.n_experiments, args.output_dir)
-    {
-        eprintln!("Error while running experiment: {}", e);
+    if !quiet {
+        println!(
+            "Running {} experiment{} with {} generations",
+            CONFIG.simulation.n_experiments,
+            if CONFIG.simulation.n_experiments > 1 {
+                "s"
+            } else {
+                ""
+            },
+            CONFIG.experiment.n_generations
+        );
+    }
+
+    let status = run_experiments_incremental_output(
+        CONFIG.simulation.n_experiments,
+        output_dir.clone(),
+        resume,
+        format,
+        quiet,
+    )
+    .map_err(|e| RunError::Io(format!("Error while running experiment: {}", e)))?;
+    if status == RunStatus::Crashed {
+        return Err(RunError::Simulation(match output_dir {
+            Some(output_dir) => format!(
+                "One or more experiments crashed, see {}",
+                output_dir.join("manifest.json").display()
+            ),
+            None => "One or more experiments crashed".to_string(),
+        }));
+    }
+    Ok(())
+}
+
+fn validate(config_path: &Path) -> Result<(), RunError> {
+    Config::load(Some(config_path), &[])
+        .map_err(|issues| config_error(Some(config_path), issues))?;
+    println!("{} is valid", config_path.display());
+    Ok(())
+}
+
+fn calc(config_path: Option<&Path>, args: &CommandLineArgs, json: bool) -> Result<(), RunError> {
+    let config = Config::load(config_path, &args.config_overrides())
+        .map_err(|issues| config_error(config_path, issues))?;
+    let equilibrium = Equilibrium::new(&config.experiment);
+    if json {
+        let json = serde_json::to_string_pretty(&equilibrium)
+            .map_err(|e| RunError::Io(format!("Could not write the results: {}", e)))?;
+        println!("{}", json);
+    } else {
+        print!("{}", equilibrium);
+    }
+    Ok(())
+}
+
+fn main() {
+    let args = CommandLineArgs::from_args();
+    let result = match &args.command {
+        Some(Command::Validate { config_path }) => validate(config_path),
+        Some(Command::Calc { config_path, json }) => calc(config_path.as_deref(), &args, *json),
+        None => {
+            // Load the configuration up front, so an invalid one fails before anything is written
+            Lazy::force(&CONFIG);
+            run(args)
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(e.exit_code());
     }
 }
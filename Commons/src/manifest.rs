@@ -0,0 +1,222 @@
+use std::any::Any;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::stopping::{RunSummary, StopReason};
+
+/// State of a run or of a single experiment. A run whose process was killed stays `running`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Running,
+    Completed,
+    StoppedEarly,
+    Crashed,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ExperimentManifest {
+    pub experiment: i32,
+    pub seed: u64,
+    pub status: RunStatus,
+    pub generations_ran: Option<i32>,
+    pub stop_reason: Option<StopReason>,
+    /// Wall-clock time spent on the experiment, summed over resumed sessions
+    pub wall_seconds: f64,
+    /// The panic message of a crashed experiment
+    pub error: Option<String>,
+}
+
+/// A later invocation that continued the run from its checkpoints
+#[derive(Serialize, Deserialize)]
+pub struct Resume {
+    pub invocation: Vec<String>,
+    pub started_at: String,
+}
+
+/// Provenance of a run, written to `manifest.json` next to `experiment.toml`
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub crate_version: String,
+    /// The commit the binary was built from, if it was built from a git checkout
+    pub git_commit: Option<String>,
+    pub invocation: Vec<String>,
+    /// Threads available on the host, and threads the experiments ran on
+    pub host_threads: usize,
+    pub worker_threads: usize,
+    pub started_at: String,
+    pub ended_at: Option<String>,
+    pub status: RunStatus,
+    pub experiments: Vec<ExperimentManifest>,
+    #[serde(default)]
+    pub resumes: Vec<Resume>,
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// The message of a caught panic
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => match payload.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => "unknown panic".to_string(),
+        },
+    }
+}
+
+impl Manifest {
+    /// Describe a new run of experiments with the given seeds
+    pub fn new(seeds: &[u64]) -> Manifest {
+        Manifest {
+            crate_version: env!("CARGO_PKG_VERSION").to_string(),
+            git_commit: option_env!("GIT_COMMIT").map(|commit| commit.to_string()),
+            invocation: std::env::args().collect(),
+            host_threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            worker_threads: rayon::current_num_threads(),
+            started_at: now(),
+            ended_at: None,
+            status: RunStatus::Running,
+            experiments: seeds
+                .iter()
+                .enumerate()
+                .map(|(exp_idx, &seed)| ExperimentManifest {
+                    experiment: exp_idx as i32,
+                    seed,
+                    status: RunStatus::Running,
+                    generations_ran: None,
+                    stop_reason: None,
+                    wall_seconds: 0.,
+                    error: None,
+                })
+                .collect(),
+            resumes: Vec::new(),
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Manifest, Box<dyn Error>> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+
+    /// Write the manifest next to its destination first and then move it in place
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
+    }
+
+    pub fn seeds(&self) -> Vec<u64> {
+        self.experiments.iter().map(|exp| exp.seed).collect()
+    }
+}
+
+/// The manifest of a running run, updated by the experiment threads as they end. Without a path
+/// it is only kept in memory.
+pub struct ManifestWriter {
+    path: Option<PathBuf>,
+    manifest: Mutex<Manifest>,
+}
+
+impl ManifestWriter {
+    pub fn create(
+        path: Option<PathBuf>,
+        manifest: Manifest,
+    ) -> Result<ManifestWriter, Box<dyn Error>> {
+        if let Some(path) = &path {
+            manifest.save(path)?;
+        }
+        Ok(ManifestWriter {
+            path,
+            manifest: Mutex::new(manifest),
+        })
+    }
+
+    /// Continue the manifest of an interrupted run, recording this invocation as a resume
+    pub fn resume(path: PathBuf) -> Result<ManifestWriter, Box<dyn Error>> {
+        let mut manifest = Manifest::load(&path)?;
+        manifest.resumes.push(Resume {
+            invocation: std::env::args().collect(),
+            started_at: now(),
+        });
+        manifest.ended_at = None;
+        manifest.status = RunStatus::Running;
+        Self::create(Some(path), manifest)
+    }
+
+    pub fn seeds(&self) -> Vec<u64> {
+        self.manifest.lock().unwrap().seeds()
+    }
+
+    fn save(&self, manifest: &Manifest) -> Result<(), Box<dyn Error>> {
+        match &self.path {
+            Some(path) => manifest.save(path),
+            None => Ok(()),
+        }
+    }
+
+    /// Record how an experiment ended: with its run summary, or with the message it crashed with
+    pub fn record_experiment(
+        &self,
+        exp_idx: i32,
+        wall_time: Duration,
+        outcome: Result<RunSummary, String>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut manifest = self.manifest.lock().unwrap();
+        let exp = &mut manifest.experiments[exp_idx as usize];
+        exp.wall_seconds += wall_time.as_secs_f64();
+        match outcome {
+            Ok(summary) => {
+                exp.status = if summary.stopped_early {
+                    RunStatus::StoppedEarly
+                } else {
+                    RunStatus::Completed
+                };
+                exp.generations_ran = Some(summary.generations_ran);
+                exp.stop_reason = summary.stop_reason;
+                exp.error = None;
+            }
+            Err(error) => {
+                exp.status = RunStatus::Crashed;
+                exp.generations_ran = None;
+                exp.stop_reason = None;
+                exp.error = Some(error);
+            }
+        }
+        self.save(&manifest)
+    }
+
+    /// Record the end of the run. It crashed if the run itself or any experiment did,
+    /// and stopped early if any experiment did.
+    pub fn finish(&self, run_failed: bool) -> Result<RunStatus, Box<dyn Error>> {
+        let mut manifest = self.manifest.lock().unwrap();
+        let statuses: Vec<RunStatus> = manifest.experiments.iter().map(|exp| exp.status).collect();
+        manifest.status = if run_failed
+            || statuses
+                .iter()
+                .any(|&status| status == RunStatus::Crashed || status == RunStatus::Running)
+        {
+            RunStatus::Crashed
+        } else if statuses.contains(&RunStatus::StoppedEarly) {
+            RunStatus::StoppedEarly
+        } else {
+            RunStatus::Completed
+        };
+        manifest.ended_at = Some(now());
+        self.save(&manifest)?;
+        Ok(manifest.status)
+    }
+}
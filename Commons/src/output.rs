@@ -0,0 +1,194 @@
+use std::error::Error;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use csv::WriterBuilder;
+
+use crate::aggregate::GenerationSender;
+use crate::statistics::{
+    AgeStatistics, AgentStatistics, EpochStatistics, GenerationStatistics, RLStatistics,
+    SignalStatistics, VotingStatistics,
+};
+use crate::table::{OutputFormat, Table};
+use crate::CONFIG;
+
+/// The tables an experiment streams its statistics to while it runs
+pub struct ExperimentWriters {
+    pub gen_stats: Table,
+    pub eval_stats: Option<Table>,
+    pub agent_stats: Option<Table>,
+    pub epoch_trace: Option<Table>,
+    pub qtable_history: Option<Table>,
+    pub signal_stats: Option<Table>,
+    pub reputation_stats: Option<Table>,
+    pub voting_stats: Option<Table>,
+    pub age_stats: Option<Table>,
+    /// Streams the generation stats to the aggregator averaging over experiments
+    pub generations: GenerationSender,
+}
+
+impl ExperimentWriters {
+    /// Open the output files of an experiment in `exp_output_dir`. A fresh experiment starts
+    /// new files, a resumed experiment appends to the rows written before its checkpoint.
+    pub fn open(
+        exp_output_dir: &Path,
+        start_generation: i32,
+        format: &OutputFormat,
+        generations: GenerationSender,
+    ) -> Result<ExperimentWriters, Box<dyn Error>> {
+        let open = |name: &str, header: Vec<String>, keep_below: i32| {
+            open_table(exp_output_dir, name, header, keep_below, format)
+        };
+        let gen_stats = open(
+            "gen_stats",
+            GenerationStatistics::csv_header(),
+            start_generation,
+        )?;
+        // Evaluations are numbered by the generations trained before them, so the
+        // evaluation at the checkpoint itself is kept as well
+        let eval_stats = if CONFIG.evaluation.enabled() {
+            Some(open(
+                "eval_stats",
+                GenerationStatistics::eval_csv_header(),
+                start_generation + 1,
+            )?)
+        } else {
+            None
+        };
+
+        let agent_stats = if CONFIG.output.agent_stats {
+            Some(open(
+                "agent_stats",
+                AgentStatistics::csv_header(),
+                start_generation,
+            )?)
+        } else {
+            None
+        };
+
+        let epoch_trace = if CONFIG.output.epoch_trace_enabled() {
+            Some(open(
+                "epoch_trace",
+                EpochStatistics::csv_header(),
+                start_generation,
+            )?)
+        } else {
+            None
+        };
+
+        let qtable_history = if CONFIG.output.qtable_snapshot_interval > 0 {
+            Some(open(
+                "qtable_history",
+                RLStatistics::history_csv_header(),
+                start_generation,
+            )?)
+        } else {
+            None
+        };
+
+        let signal_stats = if CONFIG.communication.enabled() {
+            Some(open(
+                "signal_stats",
+                SignalStatistics::csv_header(),
+                start_generation,
+            )?)
+        } else {
+            None
+        };
+
+        let reputation_stats = if CONFIG.reputation.enabled {
+            Some(open(
+                "reputation_stats",
+                AgentStatistics::reputation_csv_header(),
+                start_generation,
+            )?)
+        } else {
+            None
+        };
+
+        let voting_stats = if CONFIG.voting.enabled() {
+            Some(open(
+                "voting_stats",
+                VotingStatistics::csv_header(),
+                start_generation,
+            )?)
+        } else {
+            None
+        };
+
+        let age_stats = if CONFIG.ageing.enabled() {
+            Some(open(
+                "age_stats",
+                AgeStatistics::csv_header(),
+                start_generation,
+            )?)
+        } else {
+            None
+        };
+
+        Ok(ExperimentWriters {
+            gen_stats,
+            eval_stats,
+            agent_stats,
+            epoch_trace,
+            qtable_history,
+            signal_stats,
+            reputation_stats,
+            voting_stats,
+            age_stats,
+            generations,
+        })
+    }
+
+    fn tables(&mut self) -> Vec<&mut Table> {
+        let mut tables = vec![&mut self.gen_stats];
+        let optional_tables = vec![
+            &mut self.eval_stats,
+            &mut self.agent_stats,
+            &mut self.epoch_trace,
+            &mut self.qtable_history,
+            &mut self.signal_stats,
+            &mut self.reputation_stats,
+            &mut self.voting_stats,
+            &mut self.age_stats,
+        ];
+        tables.extend(optional_tables.into_iter().flatten());
+        tables
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        for table in self.tables() {
+            table.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Close all tables once the experiment has ended
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        for table in self.tables() {
+            table.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Open the table `name` in `dir` for writing. When resuming (`keep_below` > 0) an existing
+/// csv file is cut back to the rows whose first column is below `keep_below` and appended to,
+/// otherwise a new table is started with the given header.
+fn open_table(
+    dir: &Path,
+    name: &str,
+    header: Vec<String>,
+    keep_below: i32,
+    format: &OutputFormat,
+) -> Result<Table, Box<dyn Error>> {
+    let path = dir.join(format!("{}.csv", name));
+    if keep_below > 0 && matches!(format, OutputFormat::Csv) && path.exists() {
+        GenerationStatistics::truncate_csv(&path, keep_below)?;
+        let writer = WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(OpenOptions::new().append(true).open(&path)?);
+        return Ok(Table::Csv(Box::new(writer)));
+    }
+    Table::create(dir, name, header, format)
+}
@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rusqlite::types::{ToSqlOutput, Value as SqlValue};
+use rusqlite::{params, params_from_iter, Connection, ToSql};
+use serde::Serialize;
+
+use crate::config::Config;
+use crate::stopping::RunSummary;
+use crate::table::{serialize_row, Value, BATCH_ROWS};
+
+/// A SQLite database holding the statistics of one or more runs. Every run gets a row in
+/// `runs`, every experiment a row in `experiments`, and each statistics table has a table of
+/// its own, with the `run_id` and `experiment` (-1 for the averages of a run) of every row.
+#[derive(Clone)]
+pub struct ResultsDb {
+    connection: Arc<Mutex<Connection>>,
+    run_id: i64,
+    experiment: i32,
+}
+
+/// SQLite integers are signed, so a seed is stored with the same 64 bits as an `i64`. Seeds
+/// above `i64::MAX`, such as `seed + i` for a configured seed close to it, read back as negative.
+fn seed_column(seed: u64) -> i64 {
+    seed as i64
+}
+
+impl ResultsDb {
+    /// Open the database at `path`, creating it if needed, and register a new run in it
+    pub fn create_run(path: &Path, config: &Config) -> Result<ResultsDb, Box<dyn Error>> {
+        let connection = Connection::open(path)?;
+        // Several runs of a sweep may write to the same database at once
+        connection.busy_timeout(Duration::from_secs(60))?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS runs (
+                run_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                started_at TEXT NOT NULL DEFAULT (datetime('now')),
+                seed INTEGER,
+                config TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS experiments (
+                run_id INTEGER NOT NULL REFERENCES runs (run_id),
+                experiment INTEGER NOT NULL,
+                seed INTEGER NOT NULL,
+                generations_ran INTEGER,
+                stop_reason TEXT,
+                PRIMARY KEY (run_id, experiment)
+            );",
+        )?;
+        let seed = config.simulation.seed.map(seed_column);
+        connection.execute(
+            "INSERT INTO runs (seed, config) VALUES (?1, ?2)",
+            params![seed, serde_json::to_string(config)?],
+        )?;
+        let run_id = connection.last_insert_rowid();
+
+        Ok(ResultsDb {
+            connection: Arc::new(Mutex::new(connection)),
+            run_id,
+            experiment: -1,
+        })
+    }
+
+    /// Register an experiment of the run, returning a handle that writes its tables
+    pub fn add_experiment(&self, exp_idx: i32, seed: u64) -> Result<ResultsDb, Box<dyn Error>> {
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO experiments (run_id, experiment, seed) VALUES (?1, ?2, ?3)",
+            params![self.run_id, exp_idx, seed_column(seed)],
+        )?;
+        Ok(ResultsDb {
+            experiment: exp_idx,
+            ..self.clone()
+        })
+    }
+
+    /// Record how far the experiment of this handle ran and why it stopped
+    pub fn finish_experiment(&self, summary: &RunSummary) -> Result<(), Box<dyn Error>> {
+        let stop_reason = match summary.stop_reason {
+            Some(reason) => Some(serde_json::to_value(reason)?.as_str().unwrap().to_string()),
+            None => None,
+        };
+        self.connection.lock().unwrap().execute(
+            "UPDATE experiments SET generations_ran = ?1, stop_reason = ?2
+             WHERE run_id = ?3 AND experiment = ?4",
+            params![
+                summary.generations_ran,
+                stop_reason,
+                self.run_id,
+                self.experiment
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn table(&self, name: &str, header: Vec<String>) -> SqliteTable {
+        SqliteTable {
+            db: self.clone(),
+            name: name.to_string(),
+            header,
+            rows: Vec::new(),
+            created: false,
+        }
+    }
+}
+
+/// A statistics table in the results database. Rows are buffered and inserted in a
+/// single transaction; the column types are taken from the first row.
+pub struct SqliteTable {
+    db: ResultsDb,
+    name: String,
+    header: Vec<String>,
+    rows: Vec<Vec<Value>>,
+    created: bool,
+}
+
+impl SqliteTable {
+    pub fn serialize<S: Serialize>(&mut self, record: S) -> Result<(), Box<dyn Error>> {
+        self.rows.push(serialize_row(record, self.header.len())?);
+        if self.rows.len() >= BATCH_ROWS {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let connection = self.db.connection.clone();
+        let mut connection = connection.lock().unwrap();
+        let transaction = connection.transaction()?;
+        if !self.created {
+            self.create(&transaction)?;
+        }
+        {
+            let columns: Vec<String> = ["run_id", "experiment"]
+                .iter()
+                .map(|name| name.to_string())
+                .chain(self.header.iter().cloned())
+                .map(|name| quote(&name))
+                .collect();
+            let placeholders = vec!["?"; columns.len()].join(", ");
+            let mut insert = transaction.prepare(&format!(
+                "INSERT INTO {} ({}) VALUES ({})",
+                quote(&self.name),
+                columns.join(", "),
+                placeholders
+            ))?;
+            let keys = [
+                Value::Int64(self.db.run_id),
+                Value::Int32(self.db.experiment),
+            ];
+            for row in self.rows.drain(..) {
+                insert.execute(params_from_iter(keys.iter().chain(row.iter())))?;
+            }
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /// Create the table, or add the columns it lacks when an earlier run used other columns
+    fn create(&mut self, connection: &Connection) -> Result<(), Box<dyn Error>> {
+        let columns: Vec<String> = self
+            .header
+            .iter()
+            .zip(&self.rows[0])
+            .map(|(name, value)| format!("{} {}", quote(name), sql_type(value)))
+            .collect();
+        connection.execute_batch(&format!(
+            "CREATE TABLE IF NOT EXISTS {table} (run_id INTEGER NOT NULL, experiment INTEGER NOT NULL, {columns});
+            CREATE INDEX IF NOT EXISTS {index} ON {table} (run_id, experiment);",
+            table = quote(&self.name),
+            index = quote(&format!("{}_run", self.name)),
+            columns = columns.join(", ")
+        ))?;
+
+        let existing: HashSet<String> = connection
+            .prepare(&format!("PRAGMA table_info({})", quote(&self.name)))?
+            .query_map([], |row| row.get::<_, String>(1))?
+            .collect::<Result<_, _>>()?;
+        for (name, column) in self.header.iter().zip(&columns) {
+            if !existing.contains(name) {
+                connection.execute(
+                    &format!("ALTER TABLE {} ADD COLUMN {}", quote(&self.name), column),
+                    [],
+                )?;
+            }
+        }
+        self.created = true;
+        Ok(())
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_type(value: &Value) -> &'static str {
+    match value {
+        Value::Bool(_) | Value::Int32(_) | Value::Int64(_) => "INTEGER",
+        Value::Float32(_) | Value::Float64(_) => "REAL",
+        Value::Utf8(_) => "TEXT",
+    }
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::Owned(match self {
+            Value::Bool(value) => SqlValue::Integer(*value as i64),
+            Value::Int32(value) => SqlValue::Integer(*value as i64),
+            Value::Int64(value) => SqlValue::Integer(*value),
+            // Go through the shortest decimal representation, so 0.1f32 is stored
+            // as 0.1 and not as 0.10000000149011612
+            Value::Float32(value) => SqlValue::Real(value.to_string().parse().unwrap()),
+            Value::Float64(value) => SqlValue::Real(*value),
+            Value::Utf8(value) => SqlValue::Text(value.clone()),
+        }))
+    }
+}
@@ -1,12 +1,15 @@
 use std::collections::HashMap;
 use std::error::Error;
-use std::fs::File;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
 
 use csv::Writer;
-use rayon::prelude::*;
 use serde::Serialize;
 
 use crate::agent::structs::{AgentState, QTable};
+use crate::agent::Agent;
+use crate::table::{OutputFormat, Table};
 use crate::CONFIG;
 
 pub trait Statistics {
@@ -17,7 +20,20 @@ pub struct EpochStatistics {
     epoch_number: i32,
     pub alive_agents: i32,
     resources_in_pool: i32,
+    harvested: i32,
+    depleted: bool,
     chosen_actions: HashMap<String, Vec<i32>>,
+    /// The signal each agent emitted before harvesting, with the amount it then asked for
+    signalled_actions: Vec<(i32, i32)>,
+    voting: Option<EpochVoting>,
+}
+
+/// The quota in force in an epoch, with the ballots when the agents voted before it
+pub struct EpochVoting {
+    /// The options voted for, empty without an election
+    pub ballots: Vec<usize>,
+    /// The index of the enforced quota among the options
+    pub quota: usize,
 }
 
 impl EpochStatistics {
@@ -25,12 +41,73 @@ impl EpochStatistics {
         epoch_number: i32,
         alive_agents: i32,
         resources_in_pool: i32,
+        harvested: i32,
+        depleted: bool,
         chosen_actions: HashMap<String, Vec<i32>>,
+        signalled_actions: Vec<(i32, i32)>,
     ) -> EpochStatistics {
         EpochStatistics {
             epoch_number,
             alive_agents,
             resources_in_pool,
+            harvested,
+            depleted,
+            chosen_actions,
+            signalled_actions,
+            voting: None,
+        }
+    }
+
+    /// Add the quota in force in the epoch, when voting is enabled
+    pub fn with_voting(self, voting: Option<EpochVoting>) -> EpochStatistics {
+        EpochStatistics { voting, ..self }
+    }
+}
+
+#[derive(Serialize)]
+struct EpochCsvRecord {
+    gen_num: i32,
+    epoch_num: i32,
+    alive_agents: i32,
+    pool: i32,
+    harvested: i32,
+    depleted: bool,
+    chosen_actions: Vec<i32>,
+}
+
+impl EpochStatistics {
+    pub fn csv_header() -> Vec<String> {
+        let mut header: Vec<String> = vec![
+            "gen_num".to_string(),
+            "epoch_num".to_string(),
+            "alive_agents".to_string(),
+            "pool".to_string(),
+            "harvested".to_string(),
+            "depleted".to_string(),
+        ];
+        for state_key in AgentState::state_keys() {
+            for action_idx in 0..CONFIG.experiment.n_actions {
+                header.push(format!("{}_{}", state_key, action_idx));
+            }
+        }
+
+        header
+    }
+
+    fn as_csv_record(&self, generation_number: i32) -> EpochCsvRecord {
+        let mut chosen_actions: Vec<i32> =
+            Vec::with_capacity(self.chosen_actions.len() * CONFIG.experiment.n_actions as usize);
+        for state_key in AgentState::state_keys() {
+            chosen_actions.extend(&self.chosen_actions[&state_key]);
+        }
+
+        EpochCsvRecord {
+            gen_num: generation_number,
+            epoch_num: self.epoch_number,
+            alive_agents: self.alive_agents,
+            pool: self.resources_in_pool,
+            harvested: self.harvested,
+            depleted: self.depleted,
             chosen_actions,
         }
     }
@@ -45,34 +122,397 @@ impl Statistics for EpochStatistics {
     }
 }
 
+/// The outcome of a single agent in a generation
+#[derive(Serialize)]
+pub struct AgentStatistics {
+    gen_num: i32,
+    pub agent_id: i32,
+    score: i32,
+    days_lived: i32,
+    pub harvested: i32,
+    times_died: i32,
+    action_counts: Vec<i32>,
+    /// Written to the reputation stats instead
+    #[serde(skip)]
+    reputation: f32,
+    #[serde(skip)]
+    mean_reputation: f32,
+}
+
+/// The reputation of a single agent at the end of a generation, and its mean over the generation
+#[derive(Serialize)]
+struct ReputationCsvRecord {
+    gen_num: i32,
+    agent_id: i32,
+    reputation: f32,
+    mean_reputation: f32,
+}
+
+impl AgentStatistics {
+    pub fn new(generation_number: i32, agent: &Agent) -> AgentStatistics {
+        AgentStatistics {
+            gen_num: generation_number,
+            agent_id: agent.id,
+            score: agent.get_score(),
+            days_lived: agent.days_lived,
+            harvested: agent.harvested,
+            times_died: agent.times_died,
+            action_counts: agent.get_action_counts().clone(),
+            reputation: agent.reputation,
+            mean_reputation: agent.mean_reputation(),
+        }
+    }
+
+    pub fn reputation_csv_header() -> Vec<String> {
+        vec![
+            "gen_num".to_string(),
+            "agent_id".to_string(),
+            "reputation".to_string(),
+            "mean_reputation".to_string(),
+        ]
+    }
+
+    pub fn csv_header() -> Vec<String> {
+        let mut header: Vec<String> = vec![
+            "gen_num".to_string(),
+            "agent_id".to_string(),
+            "score".to_string(),
+            "days_lived".to_string(),
+            "harvested".to_string(),
+            "times_died".to_string(),
+        ];
+        for action_idx in 0..CONFIG.experiment.n_actions {
+            header.push(format!("action_{}", action_idx));
+        }
+
+        header
+    }
+}
+
+/// Gini coefficient of the given values: 0 when all are equal, approaching 1
+/// when a single value holds everything
+pub fn gini(values: &[i32]) -> f32 {
+    let total: i64 = values.iter().map(|&value| value as i64).sum();
+    if values.is_empty() || total <= 0 {
+        return 0.;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_unstable();
+    let n = sorted.len() as f64;
+    let weighted_sum: f64 = sorted
+        .iter()
+        .enumerate()
+        .map(|(idx, &value)| (idx as f64 + 1.) * value as f64)
+        .sum();
+    ((2. * weighted_sum) / (n * total as f64) - (n + 1.) / n) as f32
+}
+
+/// How the agents used the signals of the communication vocabulary in a generation
+#[derive(Serialize)]
+pub struct SignalStatistics {
+    gen_num: i32,
+    /// Number of times each signal was emitted
+    signal_counts: Vec<i32>,
+    /// Mean amount asked for after emitting each signal, -1 for unused signals
+    mean_actions: Vec<f32>,
+    /// Pearson correlation of the emitted signals and the amounts asked for, 0 when
+    /// either does not vary
+    signal_action_correlation: f32,
+}
+
+impl SignalStatistics {
+    pub fn new(generation_number: i32, signalled_actions: &[(i32, i32)]) -> SignalStatistics {
+        let vocabulary = CONFIG.communication.vocabulary as usize;
+        let mut signal_counts = vec![0; vocabulary];
+        let mut action_sums = vec![0; vocabulary];
+        for &(signal, action) in signalled_actions {
+            signal_counts[signal as usize] += 1;
+            action_sums[signal as usize] += action;
+        }
+        let mean_actions = signal_counts
+            .iter()
+            .zip(&action_sums)
+            .map(|(&count, &sum)| {
+                if count > 0 {
+                    sum as f32 / count as f32
+                } else {
+                    -1.
+                }
+            })
+            .collect();
+
+        SignalStatistics {
+            gen_num: generation_number,
+            signal_counts,
+            mean_actions,
+            signal_action_correlation: correlation(signalled_actions),
+        }
+    }
+
+    pub fn csv_header() -> Vec<String> {
+        let vocabulary = CONFIG.communication.vocabulary;
+        let mut header = vec!["gen_num".to_string()];
+        header.extend((0..vocabulary).map(|signal| format!("signal_{}", signal)));
+        header.extend((0..vocabulary).map(|signal| format!("mean_action_{}", signal)));
+        header.push("signal_action_correlation".to_string());
+        header
+    }
+}
+
+/// The elections of a generation and the quotas they enforced
+#[derive(Serialize)]
+pub struct VotingStatistics {
+    gen_num: i32,
+    elections: i32,
+    /// Number of votes for each quota option, over all elections
+    votes: Vec<i32>,
+    /// Number of elections each quota option won
+    wins: Vec<i32>,
+    /// Mean enforced quota over the epochs that ran
+    mean_quota: f32,
+}
+
+impl VotingStatistics {
+    pub fn new(generation_number: i32, epochs_stats: &[EpochStatistics]) -> VotingStatistics {
+        let quotas = &CONFIG.voting.quotas;
+        let mut elections = 0;
+        let mut votes = vec![0; quotas.len()];
+        let mut wins = vec![0; quotas.len()];
+        let mut quota_sum = 0;
+        let mut n_epochs = 0;
+        for voting in epochs_stats
+            .iter()
+            .filter_map(|epoch_stats| epoch_stats.voting.as_ref())
+        {
+            if !voting.ballots.is_empty() {
+                elections += 1;
+                wins[voting.quota] += 1;
+                for &option in &voting.ballots {
+                    votes[option] += 1;
+                }
+            }
+            quota_sum += quotas[voting.quota];
+            n_epochs += 1;
+        }
+
+        VotingStatistics {
+            gen_num: generation_number,
+            elections,
+            votes,
+            wins,
+            mean_quota: if n_epochs > 0 {
+                quota_sum as f32 / n_epochs as f32
+            } else {
+                0.
+            },
+        }
+    }
+
+    pub fn csv_header() -> Vec<String> {
+        let quotas = &CONFIG.voting.quotas;
+        let mut header = vec!["gen_num".to_string(), "elections".to_string()];
+        header.extend(quotas.iter().map(|quota| format!("votes_{}", quota)));
+        header.extend(quotas.iter().map(|quota| format!("wins_{}", quota)));
+        header.push("mean_quota".to_string());
+        header
+    }
+}
+
+/// How a life in the commons ended
+#[derive(Clone, Copy, PartialEq)]
+pub enum LifeEnd {
+    /// Starved, or was purged with an empty commons
+    Died,
+    OldAge,
+    /// Was still alive at the end of the generation
+    Survived,
+}
+
+/// The life of an agent, or of a newcomer in its place, within a generation
+pub struct Life {
+    pub age: i32,
+    pub end: LifeEnd,
+}
+
+/// Survival of the agents by age in a generation, a life table in ten age classes up to
+/// `max_age`
+#[derive(Serialize)]
+pub struct AgeStatistics {
+    gen_num: i32,
+    lives: i32,
+    died: i32,
+    died_of_age: i32,
+    newcomers: i32,
+    /// Mean age of the agents that died, of old age or otherwise, 0 if none did
+    mean_age_at_death: f32,
+    /// Number of lives that reached each age class
+    reached: Vec<i32>,
+    /// Number of lives that ended by dying, not of old age, in each age class
+    died_in_class: Vec<i32>,
+}
+
+impl AgeStatistics {
+    pub fn new(generation_number: i32, lives: &[Life]) -> AgeStatistics {
+        let class_starts = Self::class_starts();
+        let class_width = class_starts.get(1).copied().unwrap_or(1);
+        let deaths: Vec<&Life> = lives
+            .iter()
+            .filter(|life| life.end != LifeEnd::Survived)
+            .collect();
+        let died_of_age = lives
+            .iter()
+            .filter(|life| life.end == LifeEnd::OldAge)
+            .count() as i32;
+
+        AgeStatistics {
+            gen_num: generation_number,
+            lives: lives.len() as i32,
+            died: deaths.len() as i32 - died_of_age,
+            died_of_age,
+            newcomers: if CONFIG.ageing.replace {
+                died_of_age
+            } else {
+                0
+            },
+            mean_age_at_death: if deaths.is_empty() {
+                0.
+            } else {
+                deaths.iter().map(|life| life.age).sum::<i32>() as f32 / deaths.len() as f32
+            },
+            reached: class_starts
+                .iter()
+                .map(|&start| lives.iter().filter(|life| life.age >= start).count() as i32)
+                .collect(),
+            died_in_class: class_starts
+                .iter()
+                .map(|&start| {
+                    lives
+                        .iter()
+                        .filter(|life| {
+                            life.end == LifeEnd::Died
+                                && life.age >= start
+                                && life.age < start + class_width
+                        })
+                        .count() as i32
+                })
+                .collect(),
+        }
+    }
+
+    /// The first age of each age class
+    fn class_starts() -> Vec<i32> {
+        let max_age = CONFIG.ageing.max_age.max(1);
+        let class_width = (max_age + 9) / 10;
+        (0..max_age).step_by(class_width as usize).collect()
+    }
+
+    pub fn csv_header() -> Vec<String> {
+        let class_starts = Self::class_starts();
+        let mut header: Vec<String> = vec![
+            "gen_num".to_string(),
+            "lives".to_string(),
+            "died".to_string(),
+            "died_of_age".to_string(),
+            "newcomers".to_string(),
+            "mean_age_at_death".to_string(),
+        ];
+        header.extend(
+            class_starts
+                .iter()
+                .map(|start| format!("reached_{}", start)),
+        );
+        header.extend(class_starts.iter().map(|start| format!("died_{}", start)));
+        header
+    }
+}
+
+/// Pearson correlation of the pairs, 0 when either value does not vary
+fn correlation(pairs: &[(i32, i32)]) -> f32 {
+    let n = pairs.len() as f64;
+    let mean_x = pairs.iter().map(|&(x, _)| x as f64).sum::<f64>() / n;
+    let mean_y = pairs.iter().map(|&(_, y)| y as f64).sum::<f64>() / n;
+    let (mut covariance, mut variance_x, mut variance_y) = (0., 0., 0.);
+    for &(x, y) in pairs {
+        let (dx, dy) = (x as f64 - mean_x, y as f64 - mean_y);
+        covariance += dx * dy;
+        variance_x += dx * dx;
+        variance_y += dy * dy;
+    }
+    if variance_x > 0. && variance_y > 0. {
+        (covariance / (variance_x * variance_y).sqrt()) as f32
+    } else {
+        0.
+    }
+}
+
 #[derive(Serialize)]
 struct GenerationCsvRecord {
     gen_num: i32,
     epochs_ran: i32,
     reached_equilibrium: bool,
     agents_alive: i32,
+    harvest_gini: f32,
+    mean_pool: f32,
+    min_pool: i32,
+    epochs_below_low: i32,
+    total_harvest: i32,
+    harvest_efficiency: f32,
+    first_depletion: i32,
     chosen_actions: Vec<i32>,
 }
 
+/// A generation number with the values of the columns averaged over experiments
+pub type AggregateRow = (i32, Vec<f64>);
+
+/// Indicators of whether learning has converged
+#[derive(Serialize)]
+pub struct LearningDiagnostics {
+    /// Mean absolute temporal difference error over all updates in the generation
+    pub mean_abs_td_error: f32,
+    /// Number of distinct states in which any agent chose an action
+    pub states_visited: i32,
+    /// Fraction of states whose greedy action changed since the previous generation
+    pub policy_change_rate: f32,
+}
+
+impl LearningDiagnostics {
+    pub fn csv_header() -> Vec<String> {
+        vec![
+            "mean_abs_td_error".to_string(),
+            "states_visited".to_string(),
+            "policy_change_rate".to_string(),
+        ]
+    }
+}
+
 pub struct GenerationStatistics {
     generation_number: i32,
     epochs_stats: Vec<EpochStatistics>,
+    agents_stats: Vec<AgentStatistics>,
     pub reached_equilibrium: bool,
     pub agents_alive: i32,
+    max_sustainable_yield: i32,
+    pub diagnostics: LearningDiagnostics,
 }
 
 impl GenerationStatistics {
     pub fn new(
         generation_number: i32,
         epochs_stats: Vec<EpochStatistics>,
+        agents_stats: Vec<AgentStatistics>,
         reached_equilibrium: bool,
         agents_alive: i32,
+        max_sustainable_yield: i32,
+        diagnostics: LearningDiagnostics,
     ) -> GenerationStatistics {
         GenerationStatistics {
             generation_number,
             epochs_stats,
+            agents_stats,
             reached_equilibrium,
             agents_alive,
+            max_sustainable_yield,
+            diagnostics,
         }
     }
 
@@ -82,13 +522,124 @@ impl GenerationStatistics {
             "epochs_ran".to_string(),
             "reached_equilibrium".to_string(),
             "agents_alive".to_string(),
+            "harvest_gini".to_string(),
+            "mean_pool".to_string(),
+            "min_pool".to_string(),
+            "epochs_below_low".to_string(),
+            "total_harvest".to_string(),
+            "harvest_efficiency".to_string(),
+            "first_depletion".to_string(),
         ];
         for state_key in AgentState::state_keys() {
             for action_idx in 0..CONFIG.experiment.n_actions {
                 header.push(format!("{}_{}", state_key, action_idx));
             }
         }
+        if CONFIG.output.learning_diagnostics {
+            header.extend(LearningDiagnostics::csv_header());
+        }
+
+        header
+    }
+
+    /// The columns of the generation stats that are averaged over experiments
+    pub fn aggregate_header() -> Vec<String> {
+        let mut header: Vec<String> = vec![
+            "epochs_ran".to_string(),
+            "reached_equilibrium".to_string(),
+            "agents_alive".to_string(),
+            "harvest_gini".to_string(),
+            "mean_pool".to_string(),
+            "min_pool".to_string(),
+            "epochs_below_low".to_string(),
+            "total_harvest".to_string(),
+            "harvest_efficiency".to_string(),
+        ];
+        if CONFIG.output.learning_diagnostics {
+            header.extend(LearningDiagnostics::csv_header());
+        }
+        header
+    }
+
+    /// The values of the `aggregate_header` columns for this generation
+    pub fn aggregate_values(&self) -> Vec<f64> {
+        let record = self.as_csv_record();
+        let mut values: Vec<f64> = vec![
+            record.epochs_ran as f64,
+            if record.reached_equilibrium { 1. } else { 0. },
+            record.agents_alive as f64,
+            record.harvest_gini as f64,
+            record.mean_pool as f64,
+            record.min_pool as f64,
+            record.epochs_below_low as f64,
+            record.total_harvest as f64,
+            record.harvest_efficiency as f64,
+        ];
+        if CONFIG.output.learning_diagnostics {
+            values.push(self.diagnostics.mean_abs_td_error as f64);
+            values.push(self.diagnostics.states_visited as f64);
+            values.push(self.diagnostics.policy_change_rate as f64);
+        }
+        values
+    }
+
+    /// Read the generation number and `aggregate_header` columns back from a generation
+    /// stats csv, to include the generations before a checkpoint in the averages
+    pub fn read_aggregate_values(path: &Path) -> Result<Vec<AggregateRow>, Box<dyn Error>> {
+        let mut reader = csv::Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let column_idx = |name: &str| -> Result<usize, Box<dyn Error>> {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| format!("Column {} missing from {}", name, path.display()).into())
+        };
+        let gen_num_idx = column_idx("gen_num")?;
+        let value_idxs = Self::aggregate_header()
+            .iter()
+            .map(|name| column_idx(name))
+            .collect::<Result<Vec<usize>, _>>()?;
+
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let mut values = Vec::with_capacity(value_idxs.len());
+            for &idx in &value_idxs {
+                values.push(match &record[idx] {
+                    "true" => 1.,
+                    "false" => 0.,
+                    // As written, so the averages match those of an uninterrupted run
+                    value => value.parse::<f32>()? as f64,
+                });
+            }
+            rows.push((record[gen_num_idx].parse::<i32>()?, values));
+        }
+        Ok(rows)
+    }
 
+    pub fn epochs_ran(&self) -> i32 {
+        self.epochs_stats.len() as i32
+    }
+
+    /// The number of distinct states in which any agent chose an action during the given epochs
+    pub fn count_states_visited(epochs_stats: &[EpochStatistics]) -> i32 {
+        AgentState::state_keys()
+            .iter()
+            .filter(|state_key| {
+                epochs_stats.iter().any(|epoch_stats| {
+                    epoch_stats.chosen_actions[*state_key]
+                        .iter()
+                        .any(|&times_chosen| times_chosen > 0)
+                })
+            })
+            .count() as i32
+    }
+
+    /// Header of the evaluation stats, which prefixes each generation with the number
+    /// of generations trained before the evaluation
+    pub fn eval_csv_header() -> Vec<String> {
+        let mut header = vec!["trained_gens".to_string()];
+        header.extend(Self::csv_header());
         header
     }
 
@@ -111,17 +662,153 @@ impl GenerationStatistics {
             }
         }
 
+        let pools: Vec<i32> = self
+            .epochs_stats
+            .iter()
+            .map(|epoch_stats| epoch_stats.resources_in_pool)
+            .collect();
+        let low_threshold =
+            CONFIG.state_thresholds.commons_low * CONFIG.experiment.max_pool_size as f32;
+        let total_harvest: i32 = self
+            .epochs_stats
+            .iter()
+            .map(|epoch_stats| epoch_stats.harvested)
+            .sum();
+        // The sustainable benchmark is a full generation of harvesting the maximum sustainable yield
+        let sustainable_harvest =
+            self.max_sustainable_yield as f32 * CONFIG.experiment.epochs_per_gen as f32;
+
         GenerationCsvRecord {
             gen_num: self.generation_number,
             epochs_ran: self.epochs_stats.len() as i32,
             reached_equilibrium: self.reached_equilibrium,
             agents_alive: self.agents_alive,
+            harvest_gini: gini(
+                &self
+                    .agents_stats
+                    .iter()
+                    .map(|agent_stats| agent_stats.harvested)
+                    .collect::<Vec<i32>>(),
+            ),
+            mean_pool: if pools.is_empty() {
+                0.
+            } else {
+                pools.iter().sum::<i32>() as f32 / pools.len() as f32
+            },
+            min_pool: pools.iter().copied().min().unwrap_or(0),
+            epochs_below_low: pools
+                .iter()
+                .filter(|&&pool| (pool as f32) < low_threshold)
+                .count() as i32,
+            total_harvest,
+            harvest_efficiency: if sustainable_harvest > 0. {
+                total_harvest as f32 / sustainable_harvest
+            } else {
+                0.
+            },
+            // The epoch in which the agents first asked for more than the pool held, -1 if never
+            first_depletion: self
+                .epochs_stats
+                .iter()
+                .find(|epoch_stats| epoch_stats.depleted)
+                .map_or(-1, |epoch_stats| epoch_stats.epoch_number),
             chosen_actions: sum_chosen_actions,
         }
     }
 
-    pub fn append_to_csv(&self, writer: &mut Writer<File>) -> Result<(), csv::Error> {
-        writer.serialize(self.as_csv_record())
+    pub fn append_to(&self, table: &mut Table) -> Result<(), Box<dyn Error>> {
+        if CONFIG.output.learning_diagnostics {
+            table.serialize((self.as_csv_record(), &self.diagnostics))
+        } else {
+            table.serialize(self.as_csv_record())
+        }
+    }
+
+    pub fn append_agents_to(&self, table: &mut Table) -> Result<(), Box<dyn Error>> {
+        for agent_stats in &self.agents_stats {
+            table.serialize(agent_stats)?;
+        }
+        Ok(())
+    }
+
+    pub fn append_reputations_to(&self, table: &mut Table) -> Result<(), Box<dyn Error>> {
+        for agent_stats in &self.agents_stats {
+            table.serialize(ReputationCsvRecord {
+                gen_num: agent_stats.gen_num,
+                agent_id: agent_stats.agent_id,
+                reputation: agent_stats.reputation,
+                mean_reputation: agent_stats.mean_reputation,
+            })?;
+        }
+        Ok(())
+    }
+
+    pub fn append_voting_to(&self, table: &mut Table) -> Result<(), Box<dyn Error>> {
+        table.serialize(VotingStatistics::new(
+            self.generation_number,
+            &self.epochs_stats,
+        ))
+    }
+
+    pub fn append_signals_to(&self, table: &mut Table) -> Result<(), Box<dyn Error>> {
+        let signalled_actions: Vec<(i32, i32)> = self
+            .epochs_stats
+            .iter()
+            .flat_map(|epoch_stats| epoch_stats.signalled_actions.iter().copied())
+            .collect();
+        table.serialize(SignalStatistics::new(
+            self.generation_number,
+            &signalled_actions,
+        ))
+    }
+
+    pub fn append_epochs_to(&self, table: &mut Table) -> Result<(), Box<dyn Error>> {
+        for epoch_stats in &self.epochs_stats {
+            table.serialize(epoch_stats.as_csv_record(self.generation_number))?;
+        }
+        Ok(())
+    }
+
+    pub fn append_eval_to(
+        &self,
+        trained_generations: i32,
+        table: &mut Table,
+    ) -> Result<(), Box<dyn Error>> {
+        if CONFIG.output.learning_diagnostics {
+            table.serialize((trained_generations, self.as_csv_record(), &self.diagnostics))
+        } else {
+            table.serialize((trained_generations, self.as_csv_record()))
+        }
+    }
+
+    /// Cut a generation stats csv back to its header and the rows whose first column is
+    /// below `keep_below`, dropping rows written after the last checkpoint and any
+    /// partially written last line
+    pub fn truncate_csv(path: &Path, keep_below: i32) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("tmp");
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut writer = BufWriter::new(File::create(&tmp_path)?);
+        let mut line = String::new();
+        let mut is_header = true;
+        while reader.read_line(&mut line)? > 0 {
+            if !line.ends_with('\n') {
+                break;
+            }
+            let keep = is_header
+                || match line.split(',').next().map(|gen_num| gen_num.parse::<i32>()) {
+                    Some(Ok(gen_num)) => gen_num < keep_below,
+                    _ => false,
+                };
+            if keep {
+                writer.write_all(line.as_bytes())?;
+            }
+            is_header = false;
+            line.clear();
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&tmp_path, path)?;
+        Ok(())
     }
 }
 
@@ -204,7 +891,9 @@ impl ExperimentOutput for ExperimentStatistics {
     }
 
     fn rl_stats_to_csv(&self, output_path: &std::path::PathBuf) -> Result<(), Box<dyn Error>> {
-        self.rl_stats.to_csv(output_path)
+        let name = output_path.file_stem().unwrap().to_string_lossy();
+        let dir = output_path.parent().unwrap_or_else(|| Path::new(""));
+        self.rl_stats.write_table(dir, &name, &OutputFormat::Csv)
     }
 }
 
@@ -218,75 +907,11 @@ impl Statistics for ExperimentStatistics {
 }
 
 #[derive(Serialize)]
-pub struct AverageGenerationStatistics {
-    generation_number: i32,
-    avg_epochs_ran: f32,
-    avg_agents_alive: f32,
-}
-
-pub struct AverageExperimentStatistics {
-    avg_gen_stats: Vec<AverageGenerationStatistics>,
-    avg_rl_stats: RLStatistics,
-}
-
-impl AverageExperimentStatistics {
-    pub fn from_vector(
-        experiments_stats: &Vec<ExperimentStatistics>,
-    ) -> AverageExperimentStatistics {
-        let n_gens = experiments_stats[0].generations_stats.len();
-        let n_experiments = experiments_stats.len();
-        if experiments_stats
-            .iter()
-            .any(|stats| stats.generations_stats.len() != n_gens)
-        {
-            panic!("Not all experiments ran for the same number of generations.");
-        }
-
-        let avg_gen_stats: Vec<AverageGenerationStatistics> = (0..n_gens)
-            .into_par_iter()
-            .map(|gen_idx| {
-                let mut sum_epochs_ran: f32 = 0.;
-                let mut sum_agents_alive: f32 = 0.;
-                for stats in experiments_stats {
-                    let gen_stats = &stats.generations_stats[gen_idx];
-                    sum_epochs_ran += gen_stats.epochs_stats.len() as f32;
-                    sum_agents_alive += gen_stats.agents_alive as f32;
-                }
-
-                AverageGenerationStatistics {
-                    generation_number: gen_idx as i32,
-                    avg_epochs_ran: sum_epochs_ran / n_experiments as f32,
-                    avg_agents_alive: sum_agents_alive / n_experiments as f32,
-                }
-            })
-            .collect();
-
-        let q_tables: Vec<&QTable> = experiments_stats
-            .iter()
-            .map(|stats| stats.rl_stats.get_q_table())
-            .collect();
-
-        AverageExperimentStatistics {
-            avg_gen_stats,
-            avg_rl_stats: RLStatistics::new(QTable::average_from_vector(&q_tables)),
-        }
-    }
-}
-
-impl ExperimentOutput for AverageExperimentStatistics {
-    fn gen_stats_to_csv(&self, output_path: &std::path::PathBuf) -> Result<(), Box<dyn Error>> {
-        let mut out_writer = Writer::from_path(output_path)?;
-        for gen in &self.avg_gen_stats {
-            out_writer.serialize(gen)?;
-        }
-
-        out_writer.flush()?;
-        Ok(())
-    }
-
-    fn rl_stats_to_csv(&self, output_path: &std::path::PathBuf) -> Result<(), Box<dyn Error>> {
-        self.avg_rl_stats.to_csv(output_path)
-    }
+struct RLLongCsvRecord {
+    state: String,
+    action: i32,
+    q_value: f32,
+    visits: i32,
 }
 
 pub struct RLStatistics {
@@ -309,31 +934,123 @@ impl RLStatistics {
         }
     }
 
-    fn csv_head(&self) -> Vec<String> {
-        let mut head: Vec<String> = Vec::new();
-        head.push("action_num".to_string());
-        for key in self.q_table.state_action_pairs.keys() {
-            head.push(key.clone());
-        }
+    /// Wide layout: one row per action, with the expected value and the visit count of
+    /// that action in each state as columns, in the same state order as the generation stats
+    fn table_head(&self) -> Vec<String> {
+        let state_keys = AgentState::state_keys();
+        let mut head: Vec<String> = vec!["action".to_string()];
+        head.extend(state_keys.iter().cloned());
+        head.extend(state_keys.iter().map(|key| format!("{}_visits", key)));
         head
     }
 
-    fn as_csv_record(&self, action_num: usize) -> Vec<f32> {
-        let mut action_evs: Vec<f32> = Vec::new();
-        action_evs.push(action_num as f32);
-        for (_key, value) in &self.q_table.state_action_pairs {
-            action_evs.push(value[action_num].get_expected_value());
-        }
-        action_evs
+    fn as_csv_record(&self, state_keys: &[String], action_idx: usize) -> (i32, Vec<f32>, Vec<i32>) {
+        let action_evs: Vec<f32> = state_keys
+            .iter()
+            .map(|key| {
+                self.q_table
+                    .get_action(key, action_idx)
+                    .get_expected_value()
+            })
+            .collect();
+        let action_visits: Vec<i32> = state_keys
+            .iter()
+            .map(|key| self.q_table.get_action(key, action_idx).get_times_chosen())
+            .collect();
+        (
+            self.q_table
+                .get_action(&state_keys[0], action_idx)
+                .get_num_resources(),
+            action_evs,
+            action_visits,
+        )
     }
 
-    pub fn to_csv(&self, output_path: &std::path::PathBuf) -> Result<(), Box<dyn Error>> {
-        let mut out_writer = Writer::from_path(output_path)?;
-        out_writer.serialize(self.csv_head())?;
+    pub fn write_table(
+        &self,
+        dir: &Path,
+        name: &str,
+        format: &OutputFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        let state_keys = AgentState::state_keys();
+        let mut table = Table::create(dir, name, self.table_head(), format)?;
         for action_idx in 0..self.q_table.n_actions as usize {
-            out_writer.serialize(self.as_csv_record(action_idx))?;
+            table.serialize(self.as_csv_record(&state_keys, action_idx))?;
+        }
+        table.finish()
+    }
+
+    /// Long layout: one row per state-action pair, which stays readable
+    /// when the state space changes
+    fn as_long_csv_records(&self) -> Vec<RLLongCsvRecord> {
+        let mut records = Vec::new();
+        for state_key in AgentState::state_keys() {
+            for action_idx in 0..self.q_table.n_actions as usize {
+                let action = self.q_table.get_action(&state_key, action_idx);
+                records.push(RLLongCsvRecord {
+                    state: state_key.clone(),
+                    action: action.get_num_resources(),
+                    q_value: action.get_expected_value(),
+                    visits: action.get_times_chosen(),
+                });
+            }
+        }
+        records
+    }
+
+    fn long_table_head() -> Vec<String> {
+        vec![
+            "state".to_string(),
+            "action".to_string(),
+            "q_value".to_string(),
+            "visits".to_string(),
+        ]
+    }
+
+    pub fn write_long_table(
+        &self,
+        dir: &Path,
+        name: &str,
+        format: &OutputFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut table = Table::create(dir, name, Self::long_table_head(), format)?;
+        for record in self.as_long_csv_records() {
+            table.serialize(record)?;
+        }
+        table.finish()
+    }
+
+    pub fn history_csv_header() -> Vec<String> {
+        let mut header = vec!["gen_num".to_string(), "agent_id".to_string()];
+        header.extend(Self::long_table_head());
+        header
+    }
+
+    /// Append the long layout as a snapshot of the Q-table of `agent_id` (-1 for the
+    /// average over all agents) after generation `generation_number`
+    pub fn append_history_to(
+        &self,
+        generation_number: i32,
+        agent_id: i32,
+        table: &mut Table,
+    ) -> Result<(), Box<dyn Error>> {
+        for record in self.as_long_csv_records() {
+            table.serialize((generation_number, agent_id, record))?;
+        }
+        Ok(())
+    }
+
+    /// Write the wide layout to `<name>`, and the long layout to `<name>_long` if enabled
+    pub fn write_tables(
+        &self,
+        output_dir: &Path,
+        name: &str,
+        format: &OutputFormat,
+    ) -> Result<(), Box<dyn Error>> {
+        self.write_table(output_dir, name, format)?;
+        if CONFIG.output.rl_stats_long {
+            self.write_long_table(output_dir, &format!("{}_long", name), format)?;
         }
-        out_writer.flush()?;
         Ok(())
     }
 
@@ -341,3 +1058,49 @@ impl RLStatistics {
         &self.q_table
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncate_csv_keeps_the_checkpointed_generations() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gen_stats.csv");
+        fs::write(&path, "gen_num,epochs_ran\n0,100\n1,100\n2,100\n3,10").unwrap();
+
+        GenerationStatistics::truncate_csv(&path, 2).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "gen_num,epochs_ran\n0,100\n1,100\n"
+        );
+    }
+
+    #[test]
+    fn truncate_csv_drops_a_partially_written_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("gen_stats.csv");
+        fs::write(&path, "gen_num,epochs_ran\n0,100\n1,1").unwrap();
+
+        GenerationStatistics::truncate_csv(&path, 5).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "gen_num,epochs_ran\n0,100\n"
+        );
+    }
+
+    #[test]
+    fn gini_of_equal_harvests_is_zero() {
+        assert_eq!(gini(&[3, 3, 3, 3]), 0.);
+        assert_eq!(gini(&[]), 0.);
+        assert_eq!(gini(&[0, 0]), 0.);
+    }
+
+    #[test]
+    fn gini_of_unequal_harvests() {
+        // One of n holding everything gives (n - 1) / n
+        assert!((gini(&[0, 0, 0, 8]) - 0.75).abs() < 1e-6);
+        assert!((gini(&[8, 0, 0, 0]) - 0.75).abs() < 1e-6);
+        assert!((gini(&[1, 2, 3]) - 2. / 9.).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{StoppingConfig, StoppingMetric};
+use crate::statistics::GenerationStatistics;
+
+/// Why an experiment stopped before reaching `n_generations`
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// The moving average of the stopping metric stayed within the tolerance for a full window
+    Converged,
+    /// No agent changed its greedy action in any state for a full window
+    PolicyStable,
+    /// The wall-clock budget ran out
+    WallClock,
+}
+
+/// Watches the generation statistics of an experiment for the configured stopping criteria.
+/// Stored in checkpoints, so a resumed experiment continues its windows and wall-clock budget.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ConvergenceMonitor {
+    config: StoppingConfig,
+    /// Wall-clock time spent in the sessions before this one
+    earlier_seconds: u64,
+    #[serde(skip, default = "Instant::now")]
+    start_time: Instant,
+    /// Metric values of the last `window` generations, and their sum
+    metric_window: VecDeque<f32>,
+    metric_sum: f32,
+    /// Moving averages of the last `window` generations
+    average_window: VecDeque<f32>,
+    stable_policy_generations: i32,
+}
+
+impl ConvergenceMonitor {
+    pub fn new(config: StoppingConfig) -> ConvergenceMonitor {
+        ConvergenceMonitor {
+            config,
+            earlier_seconds: 0,
+            start_time: Instant::now(),
+            metric_window: VecDeque::with_capacity(config.window.max(0) as usize),
+            metric_sum: 0.,
+            average_window: VecDeque::with_capacity(config.window.max(0) as usize),
+            stable_policy_generations: 0,
+        }
+    }
+
+    /// Record a finished generation, returning the reason to stop if any criterion is met
+    pub fn update(&mut self, gen_stats: &GenerationStatistics) -> Option<StopReason> {
+        let window = self.config.window.max(0) as usize;
+
+        if let Some(metric) = self.config.metric {
+            if window > 0 && self.metric_converged(metric_value(metric, gen_stats), window) {
+                return Some(StopReason::Converged);
+            }
+        }
+
+        if self.config.policy_stable && window > 0 {
+            if gen_stats.diagnostics.policy_change_rate == 0. {
+                self.stable_policy_generations += 1;
+            } else {
+                self.stable_policy_generations = 0;
+            }
+            if self.stable_policy_generations >= window as i32 {
+                return Some(StopReason::PolicyStable);
+            }
+        }
+
+        if self.config.max_wall_seconds > 0
+            && self.elapsed_seconds() >= self.config.max_wall_seconds
+        {
+            return Some(StopReason::WallClock);
+        }
+
+        None
+    }
+
+    /// Wall-clock time spent on the experiment, over all sessions
+    fn elapsed_seconds(&self) -> u64 {
+        self.earlier_seconds + self.start_time.elapsed().as_secs()
+    }
+
+    /// The monitor as written to a checkpoint, counting the time spent so far as earlier sessions
+    pub fn for_checkpoint(&self) -> ConvergenceMonitor {
+        ConvergenceMonitor {
+            earlier_seconds: self.elapsed_seconds(),
+            ..self.clone()
+        }
+    }
+
+    /// Whether the moving average over `window` generations has stayed within
+    /// the tolerance for the last `window` generations
+    fn metric_converged(&mut self, value: f32, window: usize) -> bool {
+        self.metric_window.push_back(value);
+        self.metric_sum += value;
+        if self.metric_window.len() > window {
+            self.metric_sum -= self.metric_window.pop_front().unwrap();
+        }
+        if self.metric_window.len() < window {
+            return false;
+        }
+
+        self.average_window
+            .push_back(self.metric_sum / window as f32);
+        if self.average_window.len() > window {
+            self.average_window.pop_front();
+        }
+        if self.average_window.len() < window {
+            return false;
+        }
+
+        let (min, max) = self
+            .average_window
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(min, max), &average| {
+                (min.min(average), max.max(average))
+            });
+        max - min <= self.config.tolerance
+    }
+}
+
+fn metric_value(metric: StoppingMetric, gen_stats: &GenerationStatistics) -> f32 {
+    match metric {
+        StoppingMetric::EpochsRan => gen_stats.epochs_ran() as f32,
+        StoppingMetric::AgentsAlive => gen_stats.agents_alive as f32,
+    }
+}
+
+/// How far an experiment ran and why it stopped, written to `run_summary.json`
+#[derive(Serialize)]
+pub struct RunSummary {
+    pub generations_ran: i32,
+    pub stopped_early: bool,
+    pub stop_reason: Option<StopReason>,
+}
+
+impl RunSummary {
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
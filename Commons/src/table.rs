@@ -0,0 +1,595 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, BooleanArray, Float32Array, Float64Array, Int32Array, Int64Array, RecordBatch,
+    StringArray,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use csv::{Writer, WriterBuilder};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression as ParquetCodec, ZstdLevel};
+use parquet::file::properties::WriterProperties;
+use serde::ser::{self, Impossible, Serialize};
+
+use crate::results_db::{ResultsDb, SqliteTable};
+
+/// File format of a statistics table, csv or parquet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TableFormat {
+    Csv,
+    Parquet,
+}
+
+impl FromStr for TableFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(TableFormat::Csv),
+            "parquet" => Ok(TableFormat::Parquet),
+            _ => Err(format!(
+                "Unknown output format {}, expected csv or parquet",
+                s
+            )),
+        }
+    }
+}
+
+/// Compression codec of the parquet columns
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    None,
+    Snappy,
+    Zstd,
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Compression::None),
+            "snappy" => Ok(Compression::Snappy),
+            "zstd" => Ok(Compression::Zstd),
+            _ => Err(format!(
+                "Unknown compression {}, expected none, snappy or zstd",
+                s
+            )),
+        }
+    }
+}
+
+/// How the statistics tables are written: as files in the output directory,
+/// or as tables in a results database
+#[derive(Clone)]
+pub enum OutputFormat {
+    Csv,
+    Parquet(Compression),
+    Sqlite(ResultsDb),
+}
+
+impl OutputFormat {
+    pub fn new(format: TableFormat, compression: Compression) -> OutputFormat {
+        match format {
+            TableFormat::Csv => OutputFormat::Csv,
+            TableFormat::Parquet => OutputFormat::Parquet(compression),
+        }
+    }
+
+    /// The output format of the tables of a single experiment, which is
+    /// registered in the results database when writing to one
+    pub fn for_experiment(&self, exp_idx: i32, seed: u64) -> Result<OutputFormat, Box<dyn Error>> {
+        match self {
+            OutputFormat::Sqlite(db) => Ok(OutputFormat::Sqlite(db.add_experiment(exp_idx, seed)?)),
+            format => Ok(format.clone()),
+        }
+    }
+}
+
+/// A table of statistics that is written row by row. Rows are any serializable record,
+/// flattened the way the csv crate flattens them, so all formats have the same columns.
+pub enum Table {
+    Csv(Box<Writer<File>>),
+    Parquet(Box<ParquetTable>),
+    Sqlite(Box<SqliteTable>),
+}
+
+impl Table {
+    /// Create a new table called `name` in `dir` with the given column names
+    pub fn create(
+        dir: &Path,
+        name: &str,
+        header: Vec<String>,
+        format: &OutputFormat,
+    ) -> Result<Table, Box<dyn Error>> {
+        match format {
+            OutputFormat::Csv => {
+                let mut writer = WriterBuilder::new()
+                    .has_headers(false)
+                    .from_path(dir.join(format!("{}.csv", name)))?;
+                writer.write_record(header)?;
+                Ok(Table::Csv(Box::new(writer)))
+            }
+            OutputFormat::Parquet(compression) => Ok(Table::Parquet(Box::new(
+                ParquetTable::create(&dir.join(format!("{}.parquet", name)), header, *compression)?,
+            ))),
+            OutputFormat::Sqlite(db) => Ok(Table::Sqlite(Box::new(db.table(name, header)))),
+        }
+    }
+
+    pub fn serialize<S: Serialize>(&mut self, record: S) -> Result<(), Box<dyn Error>> {
+        match self {
+            Table::Csv(writer) => writer.serialize(record)?,
+            Table::Parquet(table) => table.serialize(record)?,
+            Table::Sqlite(table) => table.serialize(record)?,
+        }
+        Ok(())
+    }
+
+    /// Write the buffered rows to disk. A parquet table ends its current row group,
+    /// but can only be read once it is finished.
+    pub fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Table::Csv(writer) => writer.flush()?,
+            Table::Parquet(table) => table.flush()?,
+            Table::Sqlite(table) => table.flush()?,
+        }
+        Ok(())
+    }
+
+    /// Write the remaining rows and close the table. Nothing can be written afterwards.
+    pub fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Table::Csv(writer) => writer.flush()?,
+            Table::Parquet(table) => table.finish()?,
+            Table::Sqlite(table) => table.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Rows buffered before they are handed to the parquet writer or database in one go
+pub const BATCH_ROWS: usize = 8192;
+
+/// Flatten a record into the values of a row of a table with `n_columns` columns
+pub fn serialize_row<S: Serialize>(
+    record: S,
+    n_columns: usize,
+) -> Result<Vec<Value>, Box<dyn Error>> {
+    let mut row = RowSerializer { values: Vec::new() };
+    record.serialize(&mut row)?;
+    if row.values.len() != n_columns {
+        return Err(format!(
+            "Row has {} values, the table has {} columns",
+            row.values.len(),
+            n_columns
+        )
+        .into());
+    }
+    Ok(row.values)
+}
+
+/// A parquet file with typed columns. The column types are taken from the first row.
+pub struct ParquetTable {
+    header: Vec<String>,
+    properties: Option<WriterProperties>,
+    /// The file until the schema is known, after which the writer owns it
+    file: Option<File>,
+    writer: Option<ArrowWriter<File>>,
+    schema: Option<SchemaRef>,
+    columns: Vec<Column>,
+    n_rows: usize,
+}
+
+impl ParquetTable {
+    fn create(
+        path: &Path,
+        header: Vec<String>,
+        compression: Compression,
+    ) -> Result<ParquetTable, Box<dyn Error>> {
+        let codec = match compression {
+            Compression::None => ParquetCodec::UNCOMPRESSED,
+            Compression::Snappy => ParquetCodec::SNAPPY,
+            Compression::Zstd => ParquetCodec::ZSTD(ZstdLevel::default()),
+        };
+        Ok(ParquetTable {
+            header,
+            properties: Some(WriterProperties::builder().set_compression(codec).build()),
+            file: Some(File::create(path)?),
+            writer: None,
+            schema: None,
+            columns: Vec::new(),
+            n_rows: 0,
+        })
+    }
+
+    fn serialize<S: Serialize>(&mut self, record: S) -> Result<(), Box<dyn Error>> {
+        let row = serialize_row(record, self.header.len())?;
+        if self.columns.is_empty() {
+            self.columns = row.iter().map(Column::for_value).collect();
+        }
+        for ((column, value), name) in self.columns.iter_mut().zip(row).zip(&self.header) {
+            column.push(value, name)?;
+        }
+        self.n_rows += 1;
+        if self.n_rows >= BATCH_ROWS {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    /// Hand the buffered rows to the parquet writer, creating it on the first batch
+    fn write_batch(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.writer.is_none() {
+            // A table without any rows gets string columns
+            let fields: Vec<Field> = self
+                .header
+                .iter()
+                .enumerate()
+                .map(|(idx, name)| {
+                    let data_type = self
+                        .columns
+                        .get(idx)
+                        .map_or(DataType::Utf8, Column::data_type);
+                    Field::new(name, data_type, false)
+                })
+                .collect();
+            let schema = Arc::new(Schema::new(fields));
+            self.writer = Some(ArrowWriter::try_new(
+                self.file
+                    .take()
+                    .expect("Parquet table was already finished"),
+                schema.clone(),
+                self.properties.take(),
+            )?);
+            self.schema = Some(schema);
+        }
+        if self.n_rows > 0 {
+            let arrays: Vec<ArrayRef> = self.columns.iter_mut().map(Column::take_array).collect();
+            let batch = RecordBatch::try_new(self.schema.clone().unwrap(), arrays)?;
+            self.writer.as_mut().unwrap().write(&batch)?;
+            self.n_rows = 0;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn Error>> {
+        self.write_batch()?;
+        self.writer.as_mut().unwrap().flush()?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), Box<dyn Error>> {
+        if self.writer.is_none() && self.file.is_none() {
+            return Ok(());
+        }
+        self.write_batch()?;
+        self.writer.take().unwrap().close()?;
+        Ok(())
+    }
+}
+
+/// A single flattened field of a record
+pub enum Value {
+    Bool(bool),
+    Int32(i32),
+    Int64(i64),
+    Float32(f32),
+    Float64(f64),
+    Utf8(String),
+}
+
+/// The buffered values of a parquet column
+enum Column {
+    Bool(Vec<bool>),
+    Int32(Vec<i32>),
+    Int64(Vec<i64>),
+    Float32(Vec<f32>),
+    Float64(Vec<f64>),
+    Utf8(Vec<String>),
+}
+
+impl Column {
+    fn for_value(value: &Value) -> Column {
+        match value {
+            Value::Bool(_) => Column::Bool(Vec::new()),
+            Value::Int32(_) => Column::Int32(Vec::new()),
+            Value::Int64(_) => Column::Int64(Vec::new()),
+            Value::Float32(_) => Column::Float32(Vec::new()),
+            Value::Float64(_) => Column::Float64(Vec::new()),
+            Value::Utf8(_) => Column::Utf8(Vec::new()),
+        }
+    }
+
+    fn data_type(&self) -> DataType {
+        match self {
+            Column::Bool(_) => DataType::Boolean,
+            Column::Int32(_) => DataType::Int32,
+            Column::Int64(_) => DataType::Int64,
+            Column::Float32(_) => DataType::Float32,
+            Column::Float64(_) => DataType::Float64,
+            Column::Utf8(_) => DataType::Utf8,
+        }
+    }
+
+    fn push(&mut self, value: Value, name: &str) -> Result<(), Box<dyn Error>> {
+        match (self, value) {
+            (Column::Bool(values), Value::Bool(value)) => values.push(value),
+            (Column::Int32(values), Value::Int32(value)) => values.push(value),
+            (Column::Int64(values), Value::Int64(value)) => values.push(value),
+            (Column::Float32(values), Value::Float32(value)) => values.push(value),
+            (Column::Float64(values), Value::Float64(value)) => values.push(value),
+            (Column::Utf8(values), Value::Utf8(value)) => values.push(value),
+            (column, _) => {
+                return Err(format!(
+                    "Column {} holds {} values, got a value of another type",
+                    name,
+                    column.data_type()
+                )
+                .into())
+            }
+        }
+        Ok(())
+    }
+
+    /// Move the buffered values into an arrow array
+    fn take_array(&mut self) -> ArrayRef {
+        match self {
+            Column::Bool(values) => Arc::new(BooleanArray::from(std::mem::take(values))),
+            Column::Int32(values) => Arc::new(Int32Array::from(std::mem::take(values))),
+            Column::Int64(values) => Arc::new(Int64Array::from(std::mem::take(values))),
+            Column::Float32(values) => Arc::new(Float32Array::from(std::mem::take(values))),
+            Column::Float64(values) => Arc::new(Float64Array::from(std::mem::take(values))),
+            Column::Utf8(values) => Arc::new(StringArray::from(std::mem::take(values))),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct RowError(String);
+
+impl fmt::Display for RowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Error for RowError {}
+
+impl ser::Error for RowError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        RowError(msg.to_string())
+    }
+}
+
+fn unsupported(kind: &str) -> RowError {
+    RowError(format!("Cannot write a {} to a table column", kind))
+}
+
+/// Flattens a record into the values of a single row: structs, tuples and sequences
+/// are flattened in order, the same way the csv crate writes them
+struct RowSerializer {
+    values: Vec<Value>,
+}
+
+impl ser::Serializer for &mut RowSerializer {
+    type Ok = ();
+    type Error = RowError;
+    type SerializeSeq = Self;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = Self;
+    type SerializeTupleVariant = Impossible<(), RowError>;
+    type SerializeMap = Impossible<(), RowError>;
+    type SerializeStruct = Self;
+    type SerializeStructVariant = Impossible<(), RowError>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), RowError> {
+        self.values.push(Value::Bool(v));
+        Ok(())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), RowError> {
+        self.serialize_i32(v as i32)
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), RowError> {
+        self.serialize_i32(v as i32)
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), RowError> {
+        self.values.push(Value::Int32(v));
+        Ok(())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), RowError> {
+        self.values.push(Value::Int64(v));
+        Ok(())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), RowError> {
+        self.serialize_i32(v as i32)
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), RowError> {
+        self.serialize_i32(v as i32)
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), RowError> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), RowError> {
+        match i64::try_from(v) {
+            Ok(v) => self.serialize_i64(v),
+            Err(_) => Err(RowError(format!("{} does not fit a table column", v))),
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), RowError> {
+        self.values.push(Value::Float32(v));
+        Ok(())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), RowError> {
+        self.values.push(Value::Float64(v));
+        Ok(())
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), RowError> {
+        self.serialize_str(&v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), RowError> {
+        self.values.push(Value::Utf8(v.to_string()));
+        Ok(())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<(), RowError> {
+        Err(unsupported("byte array"))
+    }
+
+    fn serialize_none(self) -> Result<(), RowError> {
+        Err(unsupported("missing value"))
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), RowError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), RowError> {
+        Err(unsupported("unit"))
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), RowError> {
+        Err(unsupported("unit struct"))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<(), RowError> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<(), RowError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<(), RowError> {
+        Err(unsupported("enum variant with data"))
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self, RowError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self, RowError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize) -> Result<Self, RowError> {
+        Ok(self)
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, RowError> {
+        Err(unsupported("enum variant with data"))
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, RowError> {
+        Err(unsupported("map"))
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self, RowError> {
+        Ok(self)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, RowError> {
+        Err(unsupported("enum variant with data"))
+    }
+}
+
+impl ser::SerializeSeq for &mut RowSerializer {
+    type Ok = ();
+    type Error = RowError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), RowError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), RowError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for &mut RowSerializer {
+    type Ok = ();
+    type Error = RowError;
+
+    fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), RowError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), RowError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for &mut RowSerializer {
+    type Ok = ();
+    type Error = RowError;
+
+    fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), RowError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), RowError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for &mut RowSerializer {
+    type Ok = ();
+    type Error = RowError;
+
+    fn serialize_field<T: ?Sized + Serialize>(
+        &mut self,
+        _key: &'static str,
+        value: &T,
+    ) -> Result<(), RowError> {
+        value.serialize(&mut **self)
+    }
+
+    fn end(self) -> Result<(), RowError> {
+        Ok(())
+    }
+}
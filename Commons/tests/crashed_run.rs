@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+
+fn commons(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_commons"))
+        .args(args)
+        .output()
+        .expect("Could not start commons")
+}
+
+/// Make the checkpoint of an experiment restart at generation 0 with agents that crash on their first epoch.
+/// Edited as text, because `serde_json::Value` cannot hold the 128 bit state of the generator
+fn corrupt_checkpoint(path: &Path) {
+    let checkpoint = fs::read_to_string(path).unwrap();
+    let mut corrupted = String::with_capacity(checkpoint.len());
+    let mut rest = checkpoint.as_str();
+    while let Some(start) = rest.find("\"action_counts\":[") {
+        let end = start + rest[start..].find(']').unwrap();
+        corrupted.push_str(&rest[..start]);
+        corrupted.push_str("\"action_counts\":[");
+        rest = &rest[end..];
+    }
+    corrupted.push_str(rest);
+    let generation = corrupted.find("\"next_generation\":").unwrap() + "\"next_generation\":".len();
+    let digits = corrupted[generation..].find(',').unwrap();
+    corrupted.replace_range(generation..generation + digits, "0");
+    fs::write(path, corrupted).unwrap();
+}
+
+#[test]
+fn run_where_every_experiment_crashes_exits_with_the_simulation_code() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("run");
+    let out_str = out.to_str().unwrap();
+
+    let first = commons(&[
+        "-q",
+        "-n",
+        "2",
+        "--set",
+        "experiment.n_generations=2",
+        "--set",
+        "experiment.epochs_per_gen=5",
+        "--set",
+        "simulation.checkpoint_interval=1",
+        out_str,
+    ]);
+    assert!(first.status.success(), "{:?}", first);
+
+    for exp_idx in 0..2 {
+        corrupt_checkpoint(&out.join(exp_idx.to_string()).join("checkpoint.json"));
+    }
+    fs::remove_file(out.join("avg_rl_stats.csv")).unwrap();
+
+    let resumed = commons(&["-q", "--resume", out_str]);
+    assert_eq!(resumed.status.code(), Some(4), "{:?}", resumed);
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(out.join("manifest.json")).unwrap()).unwrap();
+    assert_eq!(manifest["status"], "crashed");
+    assert!(!out.join("avg_rl_stats.csv").exists());
+}